@@ -10,15 +10,17 @@ fn serialize(c: &mut Criterion) {
     c.bench_function("serialize", |b| {
         b.iter(|| {
             let mut ctx = MetricContext::default();
-            ctx.put_metric("foo", 1, Unit::Seconds);
-            ctx.put_metric("bar", 2, Unit::Bytes);
+            ctx.put_metric("foo", 1, Unit::Seconds).unwrap();
+            ctx.put_metric("bar", 2, Unit::Bytes).unwrap();
             ctx.put_dimensions(dimensions! {
                 "foo" => "1"
-            });
+            })
+            .unwrap();
             ctx.put_dimensions(dimensions! {
                 "bar" => "2",
                 "baz" => "3"
-            });
+            })
+            .unwrap();
             Log.serialize(ctx);
         })
     });
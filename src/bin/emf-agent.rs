@@ -0,0 +1,351 @@
+//! A local stand-in for the CloudWatch agent
+//!
+//! Listens for newline-delimited embedded metric format documents over TCP and UDP,
+//! validates them and aggregates their metrics in memory, periodically printing a
+//! summary to stdout or writing it to a file
+//!
+//! ```sh
+//! $ emf-agent --tcp 127.0.0.1:25888 --udp 127.0.0.1:25888 --interval 10 --output metrics.txt
+//! ```
+use aws_embedded_metrics::{
+    emf::{parse, validate, MetricValue},
+    serialize::MAX_DATAGRAM_BYTES,
+};
+use std::{
+    collections::BTreeMap,
+    env,
+    fmt::Write as FmtWrite,
+    fs::OpenOptions,
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, UdpSocket},
+    process,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+const DEFAULT_ADDRESS: &str = "127.0.0.1:25888";
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(10);
+
+const USAGE: &str =
+    "usage: emf-agent [--tcp ADDR] [--udp ADDR] [--interval SECONDS] [--output PATH]";
+
+struct Options {
+    tcp: String,
+    udp: String,
+    interval: Duration,
+    output: Option<String>,
+}
+
+impl Options {
+    fn parse(args: impl IntoIterator<Item = String>) -> Result<Options, String> {
+        let mut options = Options {
+            tcp: DEFAULT_ADDRESS.into(),
+            udp: DEFAULT_ADDRESS.into(),
+            interval: DEFAULT_INTERVAL,
+            output: None,
+        };
+        let mut args = args.into_iter();
+        while let Some(flag) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("{} requires a value", flag))
+            };
+            match flag.as_str() {
+                "--tcp" => options.tcp = value()?,
+                "--udp" => options.udp = value()?,
+                "--interval" => {
+                    options.interval = value()?
+                        .parse()
+                        .ok()
+                        .filter(|secs| *secs > 0)
+                        .map(Duration::from_secs)
+                        .ok_or("--interval must be a positive whole number of seconds")?
+                }
+                "--output" => options.output = Some(value()?),
+                other => return Err(format!("unknown argument {}", other)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Stats {
+    count: u64,
+    sum: f64,
+    min: f64,
+    max: f64,
+}
+
+impl Default for Stats {
+    fn default() -> Stats {
+        Stats {
+            count: 0,
+            sum: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+}
+
+impl Stats {
+    fn add(
+        &mut self,
+        value: f64,
+        count: u64,
+    ) {
+        self.count += count;
+        self.sum += value * count as f64;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+}
+
+/// Metrics received since the last summary, keyed by namespace, dimensions and metric name
+#[derive(Default)]
+struct Summary {
+    documents: u64,
+    invalid: u64,
+    metrics: BTreeMap<(String, String, String), Stats>,
+}
+
+impl Summary {
+    fn record(
+        &mut self,
+        line: &str,
+    ) {
+        let line = line.trim();
+        if line.is_empty() {
+            return;
+        }
+        if let Err(errors) = validate(line) {
+            self.invalid += 1;
+            for err in errors {
+                eprintln!("invalid document: {}", err);
+            }
+            return;
+        }
+        let document = match parse(line) {
+            Ok(document) => document,
+            Err(err) => {
+                self.invalid += 1;
+                eprintln!("invalid document: {}", err);
+                return;
+            }
+        };
+        self.documents += 1;
+        for directive in &document.directives {
+            let dimension_sets = if directive.dimensions.is_empty() {
+                vec![String::new()]
+            } else {
+                directive
+                    .dimensions
+                    .iter()
+                    .map(|set| {
+                        set.iter()
+                            .map(|name| format!("{}={}", name, document.dimensions[name]))
+                            .collect::<Vec<_>>()
+                            .join(",")
+                    })
+                    .collect()
+            };
+            for metric in &directive.metrics {
+                for dimensions in &dimension_sets {
+                    let stats = self
+                        .metrics
+                        .entry((
+                            directive.namespace.clone(),
+                            dimensions.clone(),
+                            metric.name.clone(),
+                        ))
+                        .or_default();
+                    match &document.metrics[&metric.name] {
+                        MetricValue::Single(value) => stats.add(*value, 1),
+                        MetricValue::Many(values) => {
+                            values.iter().for_each(|value| stats.add(*value, 1))
+                        }
+                        MetricValue::Distribution { values, counts } => values
+                            .iter()
+                            .zip(counts)
+                            .for_each(|(value, count)| stats.add(*value, *count)),
+                        MetricValue::StatisticSet {
+                            min,
+                            max,
+                            sum,
+                            sample_count,
+                        } => {
+                            stats.count += sample_count;
+                            stats.sum += sum;
+                            stats.min = stats.min.min(*min);
+                            stats.max = stats.max.max(*max);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Renders and resets the summary
+    fn take(&mut self) -> String {
+        let mut out = format!(
+            "{} documents received, {} invalid\n",
+            self.documents, self.invalid
+        );
+        for ((namespace, dimensions, name), stats) in &self.metrics {
+            let _ = writeln!(
+                out,
+                "{} [{}] {}: count={} sum={} min={} max={} avg={}",
+                namespace,
+                dimensions,
+                name,
+                stats.count,
+                stats.sum,
+                stats.min,
+                stats.max,
+                stats.sum / stats.count.max(1) as f64
+            );
+        }
+        *self = Summary::default();
+        out
+    }
+}
+
+fn record(
+    summary: &Mutex<Summary>,
+    line: &str,
+) {
+    summary
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .record(line)
+}
+
+fn listen_tcp(
+    addr: &str,
+    summary: Arc<Mutex<Summary>>,
+) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    thread::spawn(move || {
+        for stream in listener.incoming().filter_map(Result::ok) {
+            let summary = summary.clone();
+            thread::spawn(move || {
+                for line in BufReader::new(stream).lines().map_while(Result::ok) {
+                    record(&summary, &line);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+fn listen_udp(
+    addr: &str,
+    summary: Arc<Mutex<Summary>>,
+) -> io::Result<()> {
+    let socket = UdpSocket::bind(addr)?;
+    thread::spawn(move || {
+        let mut buf = vec![0; MAX_DATAGRAM_BYTES];
+        while let Ok(len) = socket.recv(&mut buf) {
+            for line in String::from_utf8_lossy(&buf[..len]).lines() {
+                record(&summary, line);
+            }
+        }
+    });
+    Ok(())
+}
+
+fn run(options: Options) -> io::Result<()> {
+    let summary = Arc::new(Mutex::new(Summary::default()));
+    listen_tcp(&options.tcp, summary.clone())?;
+    listen_udp(&options.udp, summary.clone())?;
+    eprintln!(
+        "listening on tcp://{} and udp://{}",
+        options.tcp, options.udp
+    );
+    loop {
+        thread::sleep(options.interval);
+        let report = summary
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .take();
+        match &options.output {
+            Some(path) => OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?
+                .write_all(report.as_bytes())?,
+            None => print!("{}", report),
+        }
+    }
+}
+
+fn main() {
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(err) = run(options) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_embedded_metrics::{
+        dimensions,
+        log::MetricContext,
+        serialize::{Log, Serialize},
+        Unit,
+    };
+
+    #[test]
+    fn summary_aggregates_valid_documents() {
+        let mut summary = Summary::default();
+        for value in &[1, 3] {
+            let mut ctx = MetricContext::default();
+            ctx.put_dimensions(dimensions! { "Service" => "api" })
+                .unwrap();
+            ctx.put_metric("Latency", *value, Unit::Milliseconds)
+                .unwrap();
+            summary.record(&Log.serialize(ctx));
+        }
+        summary.record("{\"_aws\":{}}");
+        assert_eq!((summary.documents, summary.invalid), (2, 1));
+        assert_eq!(
+            summary.metrics[&(
+                "aws-embedded-metrics".to_string(),
+                "Service=api".to_string(),
+                "Latency".to_string()
+            )],
+            Stats {
+                count: 2,
+                sum: 4.0,
+                min: 1.0,
+                max: 3.0
+            }
+        );
+        assert!(summary.take().contains("count=2"));
+        assert_eq!(summary.documents, 0);
+    }
+
+    #[test]
+    fn options_parse_flags() {
+        let options = Options::parse(
+            vec!["--udp", "0.0.0.0:1", "--interval", "5"]
+                .into_iter()
+                .map(String::from),
+        )
+        .unwrap();
+        assert_eq!(options.tcp, DEFAULT_ADDRESS);
+        assert_eq!(options.udp, "0.0.0.0:1");
+        assert_eq!(options.interval, Duration::from_secs(5));
+        assert!(Options::parse(vec!["--bogus".to_string()]).is_err());
+        assert!(Options::parse(vec!["--interval".to_string(), "0".into()]).is_err());
+    }
+}
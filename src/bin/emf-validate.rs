@@ -0,0 +1,149 @@
+//! Validates embedded metric format documents, one per line, read from files or stdin
+//!
+//! Lines which don't mention `_aws` metadata are assumed to be ordinary log output and skipped.
+//! Exits with a non-zero status when any document is invalid. Timestamps are judged against
+//! the current time unless `--now` gives another, in milliseconds since the unix epoch, which
+//! is useful when validating old logs
+//!
+//! ```sh
+//! $ emf-validate service.log
+//! service.log:12: metric Latency has unsupported unit "Fortnights"
+//! $ emf-validate --now 1700000000000 archived.log
+//! ```
+use aws_embedded_metrics::{
+    emf::{validate_at, ValidationError},
+    Clock, FixedClock, SystemClock,
+};
+use std::{
+    env,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    process,
+};
+
+const USAGE: &str = "usage: emf-validate [--now MILLIS] [PATH...]";
+
+/// Validates each line of input, returning the line numbers of invalid documents with their errors
+fn check(
+    input: impl BufRead,
+    clock: &dyn Clock,
+) -> io::Result<Vec<(usize, Vec<ValidationError>)>> {
+    let mut failures = Vec::new();
+    for (idx, line) in input.lines().enumerate() {
+        let line = line?;
+        if !line.contains("\"_aws\"") {
+            continue;
+        }
+        if let Err(errors) = validate_at(&line, clock) {
+            failures.push((idx + 1, errors));
+        }
+    }
+    Ok(failures)
+}
+
+fn report(
+    source: &str,
+    input: impl BufRead,
+    clock: &dyn Clock,
+) -> io::Result<bool> {
+    let failures = check(input, clock)?;
+    for (line, errors) in &failures {
+        for err in errors {
+            eprintln!("{}:{}: {}", source, line, err);
+        }
+    }
+    Ok(failures.is_empty())
+}
+
+/// Splits arguments into the clock to validate timestamps against and the paths to validate
+fn parse(args: impl IntoIterator<Item = String>) -> Result<(Box<dyn Clock>, Vec<String>), String> {
+    let mut clock: Box<dyn Clock> = Box::new(SystemClock);
+    let mut paths = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--now" => {
+                let millis = args
+                    .next()
+                    .and_then(|millis| millis.parse().ok())
+                    .ok_or("--now requires milliseconds since the unix epoch")?;
+                clock = Box::new(FixedClock::from_millis(millis));
+            }
+            other if other.starts_with('-') => return Err(format!("unknown argument {}", other)),
+            _ => paths.push(arg),
+        }
+    }
+    Ok((clock, paths))
+}
+
+fn main() {
+    let (clock, paths) = match parse(env::args().skip(1)) {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            eprintln!("{}\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+    let result = if paths.is_empty() {
+        let stdin = io::stdin();
+        report("<stdin>", stdin.lock(), clock.as_ref())
+    } else {
+        paths.iter().try_fold(true, |valid, path| {
+            let file = File::open(path)?;
+            Ok(report(path, BufReader::new(file), clock.as_ref())? && valid)
+        })
+    };
+    match result {
+        Ok(true) => (),
+        Ok(false) => process::exit(1),
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(2);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_reports_line_numbers_of_invalid_documents() -> io::Result<()> {
+        let input = "starting up\n\
+            {\"_aws\":{\"Timestamp\":0,\"CloudWatchMetrics\":[]}}\n\
+            {\"level\":\"info\"}\n\
+            {\"_aws\":{\"CloudWatchMetrics\":[]}}\n";
+        let failures = check(input.as_bytes(), &SystemClock)?;
+        assert_eq!(
+            failures.iter().map(|(line, _)| *line).collect::<Vec<_>>(),
+            vec![2, 4]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn check_judges_timestamps_against_the_given_time() -> Result<(), String> {
+        let input = "{\"_aws\":{\"Timestamp\":1000,\"CloudWatchMetrics\":[]}}\n";
+        let (clock, paths) = parse(vec!["--now".to_string(), "2000".into(), "a.log".into()])?;
+        assert_eq!(paths, vec!["a.log".to_string()]);
+        assert!(check(input.as_bytes(), clock.as_ref())
+            .map_err(|err| err.to_string())?
+            .is_empty());
+        assert_eq!(
+            check(input.as_bytes(), &SystemClock)
+                .map_err(|err| err.to_string())?
+                .len(),
+            1
+        );
+        assert!(parse(vec!["--now".to_string(), "soon".into()]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_rejects_unknown_flags() {
+        assert_eq!(
+            parse(vec!["--strict".to_string(), "a.log".into()]).err(),
+            Some("unknown argument --strict".to_string())
+        );
+    }
+}
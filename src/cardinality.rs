@@ -0,0 +1,213 @@
+//! Guards against dimensions whose values have unbounded cardinality
+use crate::clock::Clock;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+    time::Duration,
+};
+
+/// Value substituted for dimension values past a guard's limit unless configured otherwise
+const DEFAULT_REPLACEMENT: &str = "Other";
+
+/// Name of the metric recorded each time a dimension value is replaced
+pub(crate) const CARDINALITY_EXCEEDED_METRIC: &str = "DimensionCardinalityExceeded";
+
+#[derive(Debug)]
+struct Window {
+    started: Duration,
+    values: HashSet<String>,
+}
+
+/// Limits the number of distinct values recorded per dimension name within a window of time
+///
+/// Once a dimension has seen `limit` distinct values, new values are replaced
+/// with "Other" until the window elapses, and a `DimensionCardinalityExceeded`
+/// count metric is recorded. Guards are meant to be shared between loggers so
+/// they can observe values across units of work. Windows are measured on the clock
+/// of the logger recording a value unless the guard is given its own clock
+///
+/// # example
+/// ```rust,edition2018
+/// use aws_embedded_metrics::{dimensions, metric_scope, CardinalityGuard};
+/// use std::{sync::Arc, time::Duration};
+///
+/// # fn main() {
+/// let guard = Arc::new(CardinalityGuard::new(100, Duration::from_secs(3600)));
+/// metric_scope(|metrics| {
+///     metrics.set_cardinality_guard(guard.clone());
+///     metrics.put_dimensions(dimensions! {
+///         "Customer" => "acme"
+///     });
+/// });
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct CardinalityGuard {
+    limit: usize,
+    window: Duration,
+    replacement: String,
+    clock: Option<Box<dyn Clock>>,
+    windows: Mutex<HashMap<String, Window>>,
+}
+
+impl CardinalityGuard {
+    /// Creates a guard allowing `limit` distinct values per dimension name within each `window`
+    pub fn new(
+        limit: usize,
+        window: Duration,
+    ) -> Self {
+        CardinalityGuard {
+            limit,
+            window,
+            replacement: DEFAULT_REPLACEMENT.into(),
+            clock: None,
+            windows: Mutex::default(),
+        }
+    }
+
+    /// Sets the value substituted for dimension values past the limit
+    pub fn with_replacement(
+        mut self,
+        replacement: impl Into<String>,
+    ) -> Self {
+        self.replacement = replacement.into();
+        self
+    }
+
+    /// Sets the clock which measures when windows elapse, in place of the clocks
+    /// of the loggers recording values
+    pub fn with_clock(
+        mut self,
+        clock: impl Clock + 'static,
+    ) -> Self {
+        self.clock = Some(Box::new(clock));
+        self
+    }
+
+    /// Returns the value to record for a dimension, and whether it was replaced,
+    /// measuring windows on `clock` unless the guard has its own
+    pub(crate) fn admit(
+        &self,
+        name: &str,
+        value: String,
+        clock: &dyn Clock,
+    ) -> (String, bool) {
+        self.resolve(name, value, clock, true)
+    }
+
+    /// Returns the value `admit` would record for a dimension, and whether it would be
+    /// replaced, without counting the value against the limit
+    pub(crate) fn peek(
+        &self,
+        name: &str,
+        value: String,
+        clock: &dyn Clock,
+    ) -> (String, bool) {
+        self.resolve(name, value, clock, false)
+    }
+
+    fn resolve(
+        &self,
+        name: &str,
+        value: String,
+        clock: &dyn Clock,
+        record: bool,
+    ) -> (String, bool) {
+        let mut windows = self
+            .windows
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let now = self.clock.as_deref().unwrap_or(clock).now();
+        let window = windows.entry(name.into()).or_insert_with(|| Window {
+            started: now,
+            values: HashSet::new(),
+        });
+        if now.saturating_sub(window.started) >= self.window {
+            window.started = now;
+            window.values.clear();
+        }
+        if window.values.contains(&value) {
+            return (value, false);
+        }
+        if window.values.len() < self.limit {
+            if record {
+                window.values.insert(value.clone());
+            }
+            return (value, false);
+        }
+        (self.replacement.clone(), true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{ManualClock, SystemClock};
+
+    #[test]
+    fn guard_replaces_values_past_limit() {
+        let guard = CardinalityGuard::new(2, Duration::from_secs(60));
+        assert_eq!(
+            guard.admit("Id", "a".into(), &SystemClock),
+            ("a".into(), false)
+        );
+        assert_eq!(
+            guard.admit("Id", "b".into(), &SystemClock),
+            ("b".into(), false)
+        );
+        assert_eq!(
+            guard.admit("Id", "a".into(), &SystemClock),
+            ("a".into(), false)
+        );
+        assert_eq!(
+            guard.admit("Id", "c".into(), &SystemClock),
+            ("Other".into(), true)
+        );
+        assert_eq!(
+            guard.admit("Other", "c".into(), &SystemClock),
+            ("c".into(), false)
+        );
+    }
+
+    #[test]
+    fn peeking_does_not_count_values() {
+        let guard = CardinalityGuard::new(1, Duration::from_secs(60));
+        assert_eq!(
+            guard.peek("Id", "a".into(), &SystemClock),
+            ("a".into(), false)
+        );
+        assert_eq!(
+            guard.peek("Id", "b".into(), &SystemClock),
+            ("b".into(), false)
+        );
+        assert_eq!(
+            guard.admit("Id", "b".into(), &SystemClock),
+            ("b".into(), false)
+        );
+        assert_eq!(
+            guard.peek("Id", "a".into(), &SystemClock),
+            ("Other".into(), true)
+        );
+    }
+
+    #[test]
+    fn guard_resets_after_window() {
+        let clock = ManualClock::default();
+        let guard = CardinalityGuard::new(1, Duration::from_secs(60))
+            .with_replacement("Rest")
+            .with_clock(clock.clone());
+        assert_eq!(
+            guard.admit("Id", "a".into(), &SystemClock),
+            ("a".into(), false)
+        );
+        assert_eq!(
+            guard.admit("Id", "b".into(), &SystemClock),
+            ("Rest".into(), true)
+        );
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(
+            guard.admit("Id", "b".into(), &SystemClock),
+            ("b".into(), false)
+        );
+    }
+}
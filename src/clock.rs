@@ -0,0 +1,127 @@
+//! Sources of the current time, so time-dependent behavior can be controlled in tests
+use std::{
+    fmt::Debug,
+    sync::{Arc, Mutex},
+    time::{Duration, UNIX_EPOCH},
+};
+
+/// Tells the time as a duration since the unix epoch
+///
+/// Clocks provide the timestamps of emitted documents, cardinality guard windows,
+/// registry flush intervals and timer durations
+pub trait Clock: Debug + Send + Sync {
+    /// The current time since the unix epoch
+    fn now(&self) -> Duration;
+
+    /// The current time in milliseconds since the unix epoch
+    fn now_millis(&self) -> u64 {
+        self.now().as_millis() as u64
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for Arc<C> {
+    fn now(&self) -> Duration {
+        (**self).now()
+    }
+}
+
+/// Tells the time using the system's wall clock
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        UNIX_EPOCH.elapsed().unwrap_or_default()
+    }
+}
+
+/// Always tells the same time
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FixedClock(pub Duration);
+
+impl FixedClock {
+    /// Creates a clock fixed at the given milliseconds since the unix epoch
+    pub fn from_millis(millis: u64) -> Self {
+        FixedClock(Duration::from_millis(millis))
+    }
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> Duration {
+        self.0
+    }
+}
+
+/// Tells a time which only moves when advanced by hand
+///
+/// Clones share the same time, so keep a clone to advance after handing one
+/// to a logger or registry
+///
+/// # example
+/// ```rust,edition2018
+/// use aws_embedded_metrics::{Clock, ManualClock};
+/// use std::time::Duration;
+///
+/// # fn main() {
+/// let clock = ManualClock::default();
+/// clock.advance(Duration::from_secs(1));
+/// assert_eq!(clock.now_millis(), 1000);
+/// # }
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct ManualClock {
+    now: Arc<Mutex<Duration>>,
+}
+
+impl ManualClock {
+    /// Creates a clock starting at the given time since the unix epoch
+    pub fn new(now: Duration) -> Self {
+        ManualClock {
+            now: Arc::new(Mutex::new(now)),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Duration> {
+        self.now
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Moves the clock forward
+    pub fn advance(
+        &self,
+        by: Duration,
+    ) {
+        *self.lock() += by;
+    }
+
+    /// Moves the clock to the given time since the unix epoch
+    pub fn set(
+        &self,
+        now: Duration,
+    ) {
+        *self.lock() = now;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        *self.lock()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manual_clock_clones_share_time() {
+        let clock = ManualClock::new(Duration::from_secs(10));
+        let shared = clock.clone();
+        clock.advance(Duration::from_millis(500));
+        assert_eq!(shared.now_millis(), 10_500);
+        shared.set(Duration::from_secs(1));
+        assert_eq!(clock.now(), Duration::from_secs(1));
+        assert_eq!(FixedClock::from_millis(42).now_millis(), 42);
+    }
+}
@@ -1,5 +1,5 @@
 use serde::Deserialize;
-use std::env::vars;
+use std::{env::vars, sync::OnceLock};
 
 #[derive(Deserialize, Default, PartialEq, Debug)]
 pub struct Config {
@@ -15,6 +15,12 @@ pub fn get() -> Config {
     from_vars(vars())
 }
 
+/// Whether `AWS_EMF_ENABLE_DEBUG_LOGGING` is set, read once per process
+pub(crate) fn debug_logging() -> bool {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    *ENABLED.get_or_init(|| get().enable_debug_logging.is_some())
+}
+
 fn from_vars(vars: impl IntoIterator<Item = (String, String)>) -> Config {
     envy::prefixed("AWS_EMF_")
         .from_iter(vars)
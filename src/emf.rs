@@ -0,0 +1,578 @@
+//! Reads and validates documents in the embedded metric format
+//!
+//! # example
+//! ```rust,edition2018
+//! use aws_embedded_metrics::{emf, Unit};
+//!
+//! # fn main() -> Result<(), aws_embedded_metrics::Error> {
+//! let document = emf::parse(
+//!     r#"{
+//!         "_aws": {
+//!             "Timestamp": 1565375354953,
+//!             "CloudWatchMetrics": [{
+//!                 "Namespace": "MyApp",
+//!                 "Dimensions": [["Operation"]],
+//!                 "Metrics": [{ "Name": "ProcessingLatency", "Unit": "Milliseconds" }]
+//!             }]
+//!         },
+//!         "Operation": "Aggregator",
+//!         "ProcessingLatency": 100,
+//!         "RequestId": "422b1569-16f6-4a03-b8f0-fe3fd9b100f8"
+//!     }"#,
+//! )?;
+//! assert_eq!(document.directives[0].namespace, "MyApp");
+//! assert_eq!(document.directives[0].metrics[0].unit, Unit::Milliseconds);
+//! assert_eq!(document.dimensions["Operation"], "Aggregator");
+//! assert_eq!(
+//!     document.metrics["ProcessingLatency"],
+//!     emf::MetricValue::Single(100.0)
+//! );
+//! assert!(document.properties.contains_key("RequestId"));
+//! # Ok(())
+//! # }
+//! ```
+use crate::{
+    clock::{Clock, SystemClock},
+    error::Error,
+    log::Unit,
+    serialize::{MAX_DIMENSIONS, MAX_VALUES},
+};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::{collections::BTreeMap, error::Error as StdError, fmt, time::Duration};
+
+/// Each directive may reference at most 100 metrics
+const MAX_METRICS: usize = 100;
+
+/// CloudWatch accepts timestamps up to two weeks in the past
+const MAX_TIMESTAMP_AGE: Duration = Duration::from_secs(14 * 24 * 60 * 60);
+
+/// CloudWatch accepts timestamps up to two hours in the future
+const MAX_TIMESTAMP_SKEW: Duration = Duration::from_secs(2 * 60 * 60);
+
+/// A metric referenced by a directive
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+pub struct MetricDefinition {
+    pub name: String,
+    #[serde(default)]
+    pub unit: Unit,
+}
+
+/// Instructs CloudWatch to extract metrics from a document under a namespace and dimension sets
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+pub struct Directive {
+    pub namespace: String,
+    pub dimensions: Vec<Vec<String>>,
+    pub metrics: Vec<MetricDefinition>,
+}
+
+/// The forms a metric's values may take
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum MetricValue {
+    Single(f64),
+    Many(Vec<f64>),
+    #[serde(rename_all = "PascalCase")]
+    Distribution {
+        values: Vec<f64>,
+        counts: Vec<u64>,
+    },
+    #[serde(rename_all = "PascalCase")]
+    StatisticSet {
+        min: f64,
+        max: f64,
+        sum: f64,
+        sample_count: u64,
+    },
+}
+
+/// A parsed embedded metric format document
+#[derive(Debug, Clone, PartialEq)]
+pub struct Document {
+    /// Milliseconds since the unix epoch
+    pub timestamp: u64,
+    pub directives: Vec<Directive>,
+    /// Metadata other than the timestamp and directives, such as `LogGroupName`
+    pub metadata: BTreeMap<String, Value>,
+    /// Values of dimensions referenced by directives
+    pub dimensions: BTreeMap<String, String>,
+    /// Values of metrics referenced by directives
+    pub metrics: BTreeMap<String, MetricValue>,
+    /// Members not referenced by any directive
+    pub properties: BTreeMap<String, Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Metadata {
+    timestamp: u64,
+    cloud_watch_metrics: Vec<Directive>,
+    #[serde(flatten)]
+    rest: BTreeMap<String, Value>,
+}
+
+#[derive(Deserialize)]
+struct Payload {
+    _aws: Metadata,
+    #[serde(flatten)]
+    members: BTreeMap<String, Value>,
+}
+
+/// Parses a single embedded metric format document, resolving the members its directives reference
+pub fn parse(document: &str) -> Result<Document, Error> {
+    let Payload { _aws, mut members } =
+        serde_json::from_str(document).map_err(|err| Error::MalformedDocument(err.to_string()))?;
+    let mut dimensions = BTreeMap::new();
+    let mut metrics = BTreeMap::new();
+    for directive in &_aws.cloud_watch_metrics {
+        for name in directive.dimensions.iter().flatten() {
+            if dimensions.contains_key(name) {
+                continue;
+            }
+            match members.remove(name) {
+                Some(Value::String(value)) => {
+                    dimensions.insert(name.clone(), value);
+                }
+                Some(_) => {
+                    return Err(Error::MalformedDocument(format!(
+                        "dimension {} is not a string",
+                        name
+                    )))
+                }
+                None => return Err(Error::MissingMember(name.clone())),
+            }
+        }
+        for MetricDefinition { name, .. } in &directive.metrics {
+            if metrics.contains_key(name) {
+                continue;
+            }
+            let value = members
+                .remove(name)
+                .ok_or_else(|| Error::MissingMember(name.clone()))?;
+            let value = serde_json::from_value(value).map_err(|_| {
+                Error::MalformedDocument(format!("metric {} does not hold numeric values", name))
+            })?;
+            if let MetricValue::Distribution { values, counts } = &value {
+                if values.len() != counts.len() {
+                    return Err(Error::MalformedDocument(format!(
+                        "metric {} holds {} values but {} counts",
+                        name,
+                        values.len(),
+                        counts.len()
+                    )));
+                }
+            }
+            metrics.insert(name.clone(), value);
+        }
+    }
+    Ok(Document {
+        timestamp: _aws.timestamp,
+        directives: _aws.cloud_watch_metrics,
+        metadata: _aws.rest,
+        dimensions,
+        metrics,
+        properties: members,
+    })
+}
+
+/// A way in which a document breaks the embedded metric format specification
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationError {
+    /// The document is not JSON or lacks required structure
+    Malformed(String),
+    /// A member referenced by a directive is absent
+    MissingMember(String),
+    /// A referenced dimension does not hold a string
+    InvalidDimension(String),
+    /// A referenced metric does not hold a number, an array of numbers,
+    /// a value/count distribution or a statistic set
+    InvalidMetric(String),
+    /// A directive references more than 100 metrics
+    TooManyMetrics { namespace: String, count: usize },
+    /// A dimension set references more than 9 dimensions
+    TooManyDimensions { namespace: String, count: usize },
+    /// A metric holds more than 100 values
+    TooManyValues { name: String, count: usize },
+    /// A metric's unit is not one CloudWatch supports
+    InvalidUnit { name: String, unit: String },
+    /// The timestamp is more than two weeks in the past or two hours in the future
+    TimestampOutOfRange(u64),
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        match self {
+            ValidationError::Malformed(reason) => write!(f, "malformed document: {}", reason),
+            ValidationError::MissingMember(name) => {
+                write!(f, "{} is referenced but not defined", name)
+            }
+            ValidationError::InvalidDimension(name) => {
+                write!(f, "dimension {} does not hold a string", name)
+            }
+            ValidationError::InvalidMetric(name) => {
+                write!(f, "metric {} does not hold numeric values", name)
+            }
+            ValidationError::TooManyMetrics { namespace, count } => write!(
+                f,
+                "directive for {} references {} metrics, more than the limit of {}",
+                namespace, count, MAX_METRICS
+            ),
+            ValidationError::TooManyDimensions { namespace, count } => write!(
+                f,
+                "dimension set for {} references {} dimensions, more than the limit of {}",
+                namespace, count, MAX_DIMENSIONS
+            ),
+            ValidationError::TooManyValues { name, count } => write!(
+                f,
+                "metric {} holds {} values, more than the limit of {}",
+                name, count, MAX_VALUES
+            ),
+            ValidationError::InvalidUnit { name, unit } => {
+                write!(f, "metric {} has unsupported unit {}", name, unit)
+            }
+            ValidationError::TimestampOutOfRange(timestamp) => write!(
+                f,
+                "timestamp {} is more than two weeks in the past or two hours in the future",
+                timestamp
+            ),
+        }
+    }
+}
+
+impl StdError for ValidationError {}
+
+/// Checks a document against the full embedded metric format specification, reporting every
+/// problem found rather than only the first
+///
+/// See the [specification](https://docs.aws.amazon.com/AmazonCloudWatch/latest/monitoring/CloudWatch_Embedded_Metric_Format_Specification.html)
+/// for more information
+pub fn validate(document: &str) -> Result<(), Vec<ValidationError>> {
+    validate_at(document, &SystemClock)
+}
+
+/// Checks a document like `validate`, judging its timestamp against the given clock
+pub fn validate_at(
+    document: &str,
+    clock: &dyn Clock,
+) -> Result<(), Vec<ValidationError>> {
+    let malformed = |reason: &str| vec![ValidationError::Malformed(reason.into())];
+    let root: Value = serde_json::from_str(document)
+        .map_err(|err| vec![ValidationError::Malformed(err.to_string())])?;
+    let root = root
+        .as_object()
+        .ok_or_else(|| malformed("document is not an object"))?;
+    let aws = root
+        .get("_aws")
+        .and_then(Value::as_object)
+        .ok_or_else(|| malformed("_aws metadata is missing"))?;
+
+    let mut errors = Vec::new();
+    match aws.get("Timestamp").and_then(Value::as_u64) {
+        Some(timestamp) => {
+            let now = clock.now_millis();
+            let oldest = now.saturating_sub(MAX_TIMESTAMP_AGE.as_millis() as u64);
+            let newest = now + MAX_TIMESTAMP_SKEW.as_millis() as u64;
+            if timestamp < oldest || timestamp > newest {
+                errors.push(ValidationError::TimestampOutOfRange(timestamp));
+            }
+        }
+        None => errors.push(ValidationError::Malformed(
+            "Timestamp is not a non-negative integer".into(),
+        )),
+    }
+    match aws.get("CloudWatchMetrics").and_then(Value::as_array) {
+        Some(directives) => {
+            for directive in directives {
+                validate_directive(root, directive, &mut errors);
+            }
+        }
+        None => errors.push(ValidationError::Malformed(
+            "CloudWatchMetrics is not an array".into(),
+        )),
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+fn validate_directive(
+    root: &Map<String, Value>,
+    directive: &Value,
+    errors: &mut Vec<ValidationError>,
+) {
+    let namespace = match directive.get("Namespace").and_then(Value::as_str) {
+        Some(namespace) if !namespace.is_empty() => namespace,
+        _ => {
+            errors.push(ValidationError::Malformed(
+                "directive Namespace is not a non-empty string".into(),
+            ));
+            ""
+        }
+    };
+    match directive.get("Dimensions").and_then(Value::as_array) {
+        Some(sets) => {
+            for set in sets {
+                let set = match set.as_array() {
+                    Some(set) => set,
+                    None => {
+                        errors.push(ValidationError::Malformed(
+                            "dimension set is not an array".into(),
+                        ));
+                        continue;
+                    }
+                };
+                if set.len() > MAX_DIMENSIONS {
+                    errors.push(ValidationError::TooManyDimensions {
+                        namespace: namespace.into(),
+                        count: set.len(),
+                    });
+                }
+                for name in set {
+                    match name.as_str() {
+                        Some(name) => match root.get(name) {
+                            Some(Value::String(_)) => (),
+                            Some(_) => errors.push(ValidationError::InvalidDimension(name.into())),
+                            None => errors.push(ValidationError::MissingMember(name.into())),
+                        },
+                        None => errors.push(ValidationError::Malformed(
+                            "dimension reference is not a string".into(),
+                        )),
+                    }
+                }
+            }
+        }
+        None => errors.push(ValidationError::Malformed(
+            "directive Dimensions is not an array".into(),
+        )),
+    }
+    match directive.get("Metrics").and_then(Value::as_array) {
+        Some(metrics) => {
+            if metrics.len() > MAX_METRICS {
+                errors.push(ValidationError::TooManyMetrics {
+                    namespace: namespace.into(),
+                    count: metrics.len(),
+                });
+            }
+            for metric in metrics {
+                validate_metric(root, metric, errors);
+            }
+        }
+        None => errors.push(ValidationError::Malformed(
+            "directive Metrics is not an array".into(),
+        )),
+    }
+}
+
+fn validate_metric(
+    root: &Map<String, Value>,
+    metric: &Value,
+    errors: &mut Vec<ValidationError>,
+) {
+    let name = match metric.get("Name").and_then(Value::as_str) {
+        Some(name) => name,
+        None => {
+            errors.push(ValidationError::Malformed(
+                "metric Name is not a string".into(),
+            ));
+            return;
+        }
+    };
+    if let Some(unit) = metric.get("Unit") {
+        if serde_json::from_value::<Unit>(unit.clone()).is_err() {
+            errors.push(ValidationError::InvalidUnit {
+                name: name.into(),
+                unit: unit.to_string(),
+            });
+        }
+    }
+    let value = match root.get(name) {
+        Some(value) => value,
+        None => {
+            errors.push(ValidationError::MissingMember(name.into()));
+            return;
+        }
+    };
+    let count = match serde_json::from_value(value.clone()) {
+        Ok(MetricValue::Many(values)) => values.len(),
+        Ok(MetricValue::Distribution { values, counts }) if values.len() == counts.len() => {
+            values.len()
+        }
+        Ok(MetricValue::Single(_)) | Ok(MetricValue::StatisticSet { .. }) => 1,
+        _ => {
+            errors.push(ValidationError::InvalidMetric(name.into()));
+            return;
+        }
+    };
+    if count > MAX_VALUES {
+        errors.push(ValidationError::TooManyValues {
+            name: name.into(),
+            count,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::FixedClock,
+        dimensions,
+        log::{Aggregation, MetricContext},
+        serialize::{Log, Serialize},
+    };
+
+    #[test]
+    fn parse_reads_serialized_contexts() -> Result<(), Error> {
+        let mut ctx = MetricContext::default();
+        ctx.set_namespace("test");
+        ctx.put_dimensions(dimensions! { "Service" => "api" })
+            .unwrap();
+        ctx.set_property("RequestId", "abc").unwrap();
+        ctx.put_metric("Latency", 1, Unit::Milliseconds).unwrap();
+        ctx.put_metric("Latency", 2, Unit::Milliseconds).unwrap();
+        ctx.put_metric_aggregated("Bytes", 5, Unit::Bytes, Aggregation::StatisticSet)
+            .unwrap();
+        ctx.put_metric_aggregated("Hits", 1, Unit::Count, Aggregation::Compressed)
+            .unwrap();
+        let document = parse(&Log.serialize(ctx))?;
+        assert_eq!(document.directives.len(), 1);
+        assert_eq!(document.directives[0].namespace, "test");
+        assert_eq!(document.directives[0].dimensions, vec![vec!["Service"]]);
+        assert_eq!(document.dimensions["Service"], "api");
+        assert_eq!(
+            document.metrics["Latency"],
+            MetricValue::Many(vec![1.0, 2.0])
+        );
+        assert_eq!(
+            document.metrics["Bytes"],
+            MetricValue::StatisticSet {
+                min: 5.0,
+                max: 5.0,
+                sum: 5.0,
+                sample_count: 1
+            }
+        );
+        assert_eq!(
+            document.metrics["Hits"],
+            MetricValue::Distribution {
+                values: vec![1.0],
+                counts: vec![1]
+            }
+        );
+        assert_eq!(document.properties["RequestId"], "abc");
+        Ok(())
+    }
+
+    #[test]
+    fn validate_accepts_serialized_contexts() {
+        let mut ctx = MetricContext::default();
+        ctx.put_dimensions(dimensions! { "Service" => "api" })
+            .unwrap();
+        ctx.put_metric("Latency", 1, Unit::Milliseconds).unwrap();
+        ctx.put_histogram("Size", 10, Unit::Bytes).unwrap();
+        assert_eq!(validate(&Log.serialize(ctx)), Ok(()));
+    }
+
+    #[test]
+    fn validate_at_judges_timestamps_by_clock() {
+        let mut ctx = MetricContext::default();
+        ctx.set_clock(FixedClock::from_millis(0));
+        ctx.put_metric("Latency", 1, Unit::Milliseconds).unwrap();
+        let document = Log.serialize(ctx);
+        assert_eq!(validate_at(&document, &FixedClock::from_millis(0)), Ok(()));
+        assert_eq!(
+            validate_at(&document, &FixedClock(MAX_TIMESTAMP_AGE * 2)),
+            Err(vec![ValidationError::TimestampOutOfRange(0)])
+        );
+    }
+
+    #[test]
+    fn validate_reports_every_error() {
+        let values = (0..101)
+            .map(|v| v.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        let document = format!(
+            r#"{{
+                "_aws": {{
+                    "Timestamp": 0,
+                    "CloudWatchMetrics": [{{
+                        "Namespace": "test",
+                        "Dimensions": [["Service", "Route"]],
+                        "Metrics": [
+                            {{ "Name": "Latency", "Unit": "Fortnights" }},
+                            {{ "Name": "Missing" }},
+                            {{ "Name": "Text" }},
+                            {{ "Name": "Many" }}
+                        ]
+                    }}]
+                }},
+                "Service": 1,
+                "Latency": 1,
+                "Text": "one",
+                "Many": [{}]
+            }}"#,
+            values
+        );
+        assert_eq!(
+            validate(&document),
+            Err(vec![
+                ValidationError::TimestampOutOfRange(0),
+                ValidationError::InvalidDimension("Service".into()),
+                ValidationError::MissingMember("Route".into()),
+                ValidationError::InvalidUnit {
+                    name: "Latency".into(),
+                    unit: "\"Fortnights\"".into()
+                },
+                ValidationError::MissingMember("Missing".into()),
+                ValidationError::InvalidMetric("Text".into()),
+                ValidationError::TooManyValues {
+                    name: "Many".into(),
+                    count: 101
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn validate_rejects_malformed_documents() {
+        for document in &["not json", "[]", r#"{"foo":1}"#] {
+            match validate(document) {
+                Err(errors) => assert!(matches!(errors[..], [ValidationError::Malformed(_)])),
+                other => panic!("unexpected result {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn parse_rejects_missing_members() {
+        assert_eq!(
+            parse(
+                r#"{"_aws":{"Timestamp":1,"CloudWatchMetrics":[{"Namespace":"a","Dimensions":[],"Metrics":[{"Name":"foo","Unit":"Count"}]}]}}"#
+            ),
+            Err(Error::MissingMember("foo".into()))
+        );
+    }
+
+    #[test]
+    fn parse_rejects_malformed_documents() {
+        for document in &[
+            "not json",
+            r#"{"foo":1}"#,
+            r#"{"_aws":{"Timestamp":1,"CloudWatchMetrics":[{"Namespace":"a","Dimensions":[],"Metrics":[{"Name":"foo"}]}]},"foo":"bar"}"#,
+            r#"{"_aws":{"Timestamp":1,"CloudWatchMetrics":[{"Namespace":"a","Dimensions":[],"Metrics":[{"Name":"foo"}]}]},"foo":{"Values":[1,2],"Counts":[1]}}"#,
+        ] {
+            match parse(document) {
+                Err(Error::MalformedDocument(_)) => (),
+                other => panic!("unexpected result {:?}", other),
+            }
+        }
+    }
+}
@@ -1,32 +1,62 @@
-use crate::{config::Config, log::MetricContext};
+use crate::{
+    config::Config,
+    log::MetricContext,
+    serialize::Log,
+    sink::{self, Sink},
+};
 use serde::Deserialize;
 use std::{
     borrow::Cow,
     env::var,
+    fmt,
     io::{BufRead, BufReader, Write},
     net::TcpStream,
+    sync::{Arc, Mutex, OnceLock},
     time::Duration,
 };
 
-pub(crate) trait EnvironmentProvider {
-    fn get(&mut self) -> Box<dyn Env>;
+/// An environment resolved along with the sink its metrics are written to
+#[derive(Clone)]
+pub(crate) struct Environment {
+    pub(crate) env: Arc<dyn Env>,
+    pub(crate) sink: Arc<Mutex<dyn Sink>>,
+}
+
+pub(crate) trait EnvironmentProvider: Send {
+    fn get(&mut self) -> Environment;
 }
 
+/// The environment detected for this process. Detection may probe the network and
+/// connect to the agent, so it happens once rather than on every flush
+static DETECTED: OnceLock<Environment> = OnceLock::new();
+
 pub(crate) struct Detector;
 
-impl EnvironmentProvider for Detector {
-    fn get(&mut self) -> Box<dyn Env> {
+impl Detector {
+    fn detect() -> Arc<dyn Env> {
         let potentials: Vec<Box<dyn Env + 'static>> = vec![Box::new(Lambda), Box::new(EC2::new())];
         for mut env in potentials.into_iter() {
             if env.probe() {
-                return env;
+                return env.into();
             }
         }
-        Box::new(Vars(crate::config::get()))
+        Arc::new(Vars(crate::config::get()))
     }
 }
 
-pub(crate) trait Env {
+impl EnvironmentProvider for Detector {
+    fn get(&mut self) -> Environment {
+        DETECTED
+            .get_or_init(|| {
+                let env = Detector::detect();
+                let sink = env.sink();
+                Environment { env, sink }
+            })
+            .clone()
+    }
+}
+
+pub(crate) trait Env: Send + Sync {
     fn probe(&mut self) -> bool;
     fn name(&self) -> Cow<'_, str>;
     fn env_type(&self) -> Cow<'_, str>;
@@ -34,7 +64,24 @@ pub(crate) trait Env {
     fn configure(
         &self,
         context: &mut MetricContext,
-    ) -> ();
+    );
+    fn sink(&self) -> Arc<Mutex<dyn Sink>>;
+}
+
+/// Resolves an agent sink, falling back on stdout when the agent is unreachable
+fn agent_sink(
+    log_group_name: String,
+    config: &Config,
+) -> Arc<Mutex<dyn Sink>> {
+    match sink::Agent::create(
+        log_group_name,
+        config.log_stream_name.clone(),
+        config.agent_endpoint.clone(),
+        Log,
+    ) {
+        Ok(agent) => Arc::new(Mutex::new(agent)),
+        Err(_) => Arc::new(Mutex::new(sink::Lambda::new(Log))),
+    }
 }
 
 pub(crate) struct Vars(Config);
@@ -45,21 +92,11 @@ impl Env for Vars {
     }
 
     fn name(&self) -> Cow<'_, str> {
-        self.0
-            .service_name
-            .as_ref()
-            .map(|s| s.as_str())
-            .unwrap_or_else(|| "Unknown")
-            .into()
+        self.0.service_name.as_deref().unwrap_or("Unknown").into()
     }
 
     fn env_type(&self) -> Cow<'_, str> {
-        self.0
-            .service_type
-            .as_ref()
-            .map(|s| s.as_str())
-            .unwrap_or_else(|| "Unknown")
-            .into()
+        self.0.service_type.as_deref().unwrap_or("Unknown").into()
     }
 
     fn log_group_name(&self) -> Cow<'_, str> {
@@ -75,6 +112,10 @@ impl Env for Vars {
         _: &mut MetricContext,
     ) {
     }
+
+    fn sink(&self) -> Arc<Mutex<dyn Sink>> {
+        agent_sink(self.log_group_name().into_owned(), &self.0)
+    }
 }
 
 pub(crate) struct Lambda;
@@ -103,18 +144,22 @@ impl Env for Lambda {
         context: &mut MetricContext,
     ) {
         if let Ok(value) = var("AWS_EXECUTION_ENV") {
-            context.set_property("executionEnvironment", value);
+            let _ = context.set_property("executionEnvironment", value);
         }
         if let Ok(value) = var("AWS_LAMBDA_FUNCTION_MEMORY_SIZE") {
-            context.set_property("memorySize", value);
+            let _ = context.set_property("memorySize", value);
         }
         if let Ok(value) = var("AWS_LAMBDA_FUNCTION_VERSION") {
-            context.set_property("functionVersion", value);
+            let _ = context.set_property("functionVersion", value);
         }
         if let Ok(value) = var("AWS_LAMBDA_LOG_STREAM_NAME") {
-            context.set_property("logStreamId", value);
+            let _ = context.set_property("logStreamId", value);
         }
     }
+
+    fn sink(&self) -> Arc<Mutex<dyn Sink>> {
+        Arc::new(Mutex::new(sink::Lambda::new(Log)))
+    }
 }
 
 #[derive(Deserialize)]
@@ -132,6 +177,18 @@ enum EC2Error {
     Parse(serde_json::Error),
 }
 
+impl fmt::Display for EC2Error {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        match self {
+            EC2Error::Io(err) => write!(f, "failed to fetch ec2 metadata: {}", err),
+            EC2Error::Parse(err) => write!(f, "failed to parse ec2 metadata: {}", err),
+        }
+    }
+}
+
 pub(crate) struct EC2 {
     config: Config,
     metadata: Option<Result<EC2MetadataResponse, EC2Error>>,
@@ -161,7 +218,7 @@ impl EC2 {
         )
         .map_err(EC2Error::Io)?;
 
-        let response = BufReader::new(conn).lines().filter_map(Result::ok).skip(9);
+        let response = BufReader::new(conn).lines().map_while(Result::ok).skip(9);
         serde_json::from_str(&response.collect::<Vec<_>>().join("")).map_err(EC2Error::Parse)
     }
 }
@@ -171,16 +228,19 @@ impl Env for EC2 {
         if self.metadata.is_some() {
             return self.metadata.as_ref().iter().any(|m| m.is_ok());
         }
-        self.metadata = Some(self.fetch());
+        let metadata = self.fetch();
+        if let (Err(err), Some(_)) = (&metadata, &self.config.enable_debug_logging) {
+            eprintln!("{}", err);
+        }
+        self.metadata = Some(metadata);
         self.probe()
     }
 
     fn name(&self) -> Cow<'_, str> {
         self.config
             .service_name
-            .as_ref()
-            .map(|s| s.as_str())
-            .unwrap_or_else(|| "Unknown")
+            .as_deref()
+            .unwrap_or("Unknown")
             .into()
     }
 
@@ -205,11 +265,15 @@ impl Env for EC2 {
         context: &mut MetricContext,
     ) {
         if let Some(Ok(metadata)) = &self.metadata {
-            context.set_property("imageId", metadata.image_id.as_str());
-            context.set_property("instanceId", metadata.instance_id.as_str());
-            context.set_property("instanceType", metadata.instance_type.as_str());
-            context.set_property("privateIP", metadata.private_ip.as_str());
-            context.set_property("availabilityZone", metadata.availability_zone.as_str());
+            let _ = context.set_property("imageId", metadata.image_id.as_str());
+            let _ = context.set_property("instanceId", metadata.instance_id.as_str());
+            let _ = context.set_property("instanceType", metadata.instance_type.as_str());
+            let _ = context.set_property("privateIP", metadata.private_ip.as_str());
+            let _ = context.set_property("availabilityZone", metadata.availability_zone.as_str());
         }
     }
+
+    fn sink(&self) -> Arc<Mutex<dyn Sink>> {
+        agent_sink(self.log_group_name().into_owned(), &self.config)
+    }
 }
@@ -0,0 +1,116 @@
+use crate::config;
+use std::{error::Error as StdError, fmt};
+
+/// Problems encountered while recording or emitting metrics
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// A property could not fit within the maximum log event size and was dropped
+    PropertyTooLarge(String),
+    /// A metric value could not fit within the maximum log event size and was dropped
+    MetricTooLarge(String),
+    /// A context's metadata and dimensions alone exceeded the maximum log event size
+    /// and nothing was emitted
+    EventTooLarge { size: usize, max_bytes: usize },
+    /// A name reserved for EMF metadata was used for a property, dimension or metric
+    ReservedKey(String),
+    /// A name was already in use by a member of another kind
+    KeyCollision(String),
+    /// A dimension name or value was invalid and its dimension set was dropped
+    InvalidDimension { name: String, reason: &'static str },
+    /// A metric name was invalid and its value was dropped
+    InvalidMetricName { name: String, reason: &'static str },
+    /// A property value was invalid and the property was dropped
+    InvalidProperty { name: String, reason: &'static str },
+    /// A metric's own dimension collided with another member of its document, such
+    /// as the same dimension with a different value, or the metric was already recorded
+    /// with other dimensions, and its value was dropped
+    ConflictingDimension { metric: String, dimension: String },
+    /// A document was not valid embedded metric format
+    MalformedDocument(String),
+    /// A member referenced by a document's directives was absent
+    MissingMember(String),
+    /// A serialized payload could not be sent to its destination
+    SendFailed(String),
+    /// A histogram's relative accuracy was not between 0 and 1
+    InvalidRelativeAccuracy(f64),
+    /// A metric value was NaN or infinite and was dropped
+    NonFiniteValue(f64),
+}
+
+impl fmt::Display for Error {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        match self {
+            Error::PropertyTooLarge(name) => write!(
+                f,
+                "property {} exceeded the maximum event size and was dropped",
+                name
+            ),
+            Error::MetricTooLarge(name) => write!(
+                f,
+                "metric {} exceeded the maximum event size and was dropped",
+                name
+            ),
+            Error::EventTooLarge { size, max_bytes } => write!(
+                f,
+                "event of {} bytes exceeded the maximum event size of {} bytes",
+                size, max_bytes
+            ),
+            Error::ReservedKey(name) => write!(f, "{} is a reserved name", name),
+            Error::KeyCollision(name) => write!(
+                f,
+                "{} is already in use by another property, dimension or metric",
+                name
+            ),
+            Error::InvalidDimension { name, reason } => {
+                write!(
+                    f,
+                    "dimension set was dropped because dimension {} {}",
+                    name, reason
+                )
+            }
+            Error::InvalidMetricName { name, reason } => {
+                write!(f, "metric {} was dropped because its name {}", name, reason)
+            }
+            Error::InvalidProperty { name, reason } => {
+                write!(f, "property {} was dropped because its value {}", name, reason)
+            }
+            Error::ConflictingDimension { metric, dimension } => write!(
+                f,
+                "metric {} was dropped because its dimension {} conflicted with another member or dimension set",
+                metric, dimension
+            ),
+            Error::MalformedDocument(reason) => write!(f, "malformed document: {}", reason),
+            Error::MissingMember(name) => {
+                write!(f, "document references {} but does not define it", name)
+            }
+            Error::SendFailed(reason) => write!(f, "failed to send metrics: {}", reason),
+            Error::InvalidRelativeAccuracy(accuracy) => write!(
+                f,
+                "relative accuracy {} is not between 0 and 1",
+                accuracy
+            ),
+            Error::NonFiniteValue(value) => {
+                write!(f, "value {} was dropped because it is not finite", value)
+            }
+        }
+    }
+}
+
+impl StdError for Error {}
+
+/// Returns errors raised while flushing, first writing them to stderr
+/// when debug logging is enabled
+pub(crate) fn report(errors: Vec<Error>) -> Result<(), Vec<Error>> {
+    if errors.is_empty() {
+        return Ok(());
+    }
+    if config::debug_logging() {
+        for err in &errors {
+            eprintln!("{}", err);
+        }
+    }
+    Err(errors)
+}
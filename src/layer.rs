@@ -0,0 +1,357 @@
+//! A `tracing` layer which emits embedded metric format documents from spans and events
+use crate::{
+    clock::{Clock, SystemClock},
+    log::{MetricLogger, Unit},
+    sink::Sink,
+};
+use serde_json::Value;
+use std::{
+    fmt::Debug,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tracing_core::{
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+    Event, Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+/// Field marking the spans which open their own metric context
+const DEFAULT_MARKER: &str = "emf";
+
+/// Prefix of fields recorded as metrics rather than properties
+const DEFAULT_METRIC_PREFIX: &str = "metric.";
+
+/// Field events carry their formatted message in, which is not recorded
+const MESSAGE_FIELD: &str = "message";
+
+/// Suffix of the metric recording a span's duration, which keeps it apart from
+/// a `metric.` field named like the span
+const DURATION_SUFFIX: &str = ".Duration";
+
+/// The metric context of a marked span
+struct SpanMetrics {
+    logger: Mutex<MetricLogger>,
+    started: Duration,
+}
+
+/// A `tracing_subscriber::Layer` which records spans and events into `MetricLogger`s
+///
+/// Each span with an `emf` field opens its own logger, which is flushed when the
+/// span closes. A string value for the field sets the logger's namespace. Loggers
+/// of nested marked spans are children of their nearest marked ancestor's logger,
+/// inheriting its namespace, dimensions and properties.
+///
+/// Fields of marked spans, and of events within them, whose names start with `metric.`
+/// and whose values are numeric are recorded as metrics without a unit. Other fields,
+/// including non-numeric `metric.` fields, are recorded as properties under their full
+/// name. The duration of each marked span is recorded in milliseconds as a metric
+/// named after the span with a `.Duration` suffix, such as `HandleRequest.Duration`
+///
+/// # example
+/// ```rust,edition2018
+/// use aws_embedded_metrics::EmfLayer;
+/// use tracing_subscriber::layer::SubscriberExt;
+///
+/// # fn main() {
+/// let subscriber = tracing_subscriber::registry().with(EmfLayer::default());
+/// tracing::subscriber::with_default(subscriber, || {
+///     let span = tracing::info_span!("HandleRequest", emf = "MyApp", request_id = "abc");
+///     let _entered = span.enter();
+///     tracing::info!(metric.items = 3, "processed items");
+/// });
+/// # }
+/// ```
+pub struct EmfLayer {
+    logger: Box<dyn Fn() -> MetricLogger + Send + Sync>,
+    clock: Arc<dyn Clock>,
+    marker: String,
+    metric_prefix: String,
+}
+
+impl Default for EmfLayer {
+    fn default() -> Self {
+        EmfLayer::new(MetricLogger::default)
+    }
+}
+
+impl EmfLayer {
+    /// Creates a layer which opens loggers for marked spans with the given function
+    pub fn new(logger: impl Fn() -> MetricLogger + Send + Sync + 'static) -> Self {
+        EmfLayer {
+            logger: Box::new(logger),
+            clock: Arc::new(SystemClock),
+            marker: DEFAULT_MARKER.into(),
+            metric_prefix: DEFAULT_METRIC_PREFIX.into(),
+        }
+    }
+
+    /// Creates a layer whose loggers all flush to clones of the given sink
+    pub fn with_sink(sink: impl Sink + Clone + Sync + 'static) -> Self {
+        EmfLayer::new(move || MetricLogger::with_sink(sink.clone()))
+    }
+
+    /// Sets the name of the field marking spans which open their own metric context
+    pub fn with_marker(
+        mut self,
+        marker: impl Into<String>,
+    ) -> Self {
+        self.marker = marker.into();
+        self
+    }
+
+    /// Sets the prefix of fields recorded as metrics rather than properties
+    pub fn with_metric_prefix(
+        mut self,
+        prefix: impl Into<String>,
+    ) -> Self {
+        self.metric_prefix = prefix.into();
+        self
+    }
+
+    /// Sets the clock used to timestamp documents and measure span durations
+    pub fn with_clock(
+        mut self,
+        clock: impl Clock + 'static,
+    ) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    fn fields<'a>(
+        &'a self,
+        logger: &'a mut MetricLogger,
+    ) -> Fields<'a> {
+        Fields {
+            logger,
+            marker: &self.marker,
+            metric_prefix: &self.metric_prefix,
+        }
+    }
+}
+
+/// Records visited fields into a logger
+struct Fields<'a> {
+    logger: &'a mut MetricLogger,
+    marker: &'a str,
+    metric_prefix: &'a str,
+}
+
+impl Fields<'_> {
+    fn record(
+        &mut self,
+        field: &Field,
+        value: Value,
+    ) {
+        let name = field.name();
+        if name == self.marker {
+            if let Value::String(namespace) = value {
+                self.logger.set_namespace(namespace);
+            }
+            return;
+        }
+        if name == MESSAGE_FIELD {
+            return;
+        }
+        // rejected fields are recorded in the logger's errors and returned by its flush
+        let _ = match (name.strip_prefix(self.metric_prefix), value.as_f64()) {
+            (Some(metric), Some(value)) => self.logger.put_metric(metric, value, Unit::None),
+            _ => self.logger.set_property(name, value),
+        };
+    }
+}
+
+impl Visit for Fields<'_> {
+    fn record_f64(
+        &mut self,
+        field: &Field,
+        value: f64,
+    ) {
+        self.record(field, value.into())
+    }
+
+    fn record_i64(
+        &mut self,
+        field: &Field,
+        value: i64,
+    ) {
+        self.record(field, value.into())
+    }
+
+    fn record_u64(
+        &mut self,
+        field: &Field,
+        value: u64,
+    ) {
+        self.record(field, value.into())
+    }
+
+    fn record_bool(
+        &mut self,
+        field: &Field,
+        value: bool,
+    ) {
+        self.record(field, value.into())
+    }
+
+    fn record_str(
+        &mut self,
+        field: &Field,
+        value: &str,
+    ) {
+        self.record(field, value.into())
+    }
+
+    fn record_debug(
+        &mut self,
+        field: &Field,
+        value: &dyn Debug,
+    ) {
+        self.record(field, format!("{:?}", value).into())
+    }
+}
+
+impl<S> Layer<S> for EmfLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(
+        &self,
+        attrs: &Attributes<'_>,
+        id: &Id,
+        ctx: Context<'_, S>,
+    ) {
+        if attrs.metadata().fields().field(&self.marker).is_none() {
+            return;
+        }
+        let span = match ctx.span(id) {
+            Some(span) => span,
+            None => return,
+        };
+        let parent = span.scope().skip(1).find_map(|ancestor| {
+            ancestor
+                .extensions()
+                .get::<SpanMetrics>()
+                .map(|metrics| lock(&metrics.logger).child())
+        });
+        let mut logger = parent.unwrap_or_else(|| (self.logger)());
+        logger.set_clock(self.clock.clone());
+        attrs.record(&mut self.fields(&mut logger));
+        span.extensions_mut().insert(SpanMetrics {
+            logger: Mutex::new(logger),
+            started: self.clock.now(),
+        });
+    }
+
+    fn on_record(
+        &self,
+        id: &Id,
+        values: &Record<'_>,
+        ctx: Context<'_, S>,
+    ) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(metrics) = span.extensions().get::<SpanMetrics>() {
+                values.record(&mut self.fields(&mut lock(&metrics.logger)));
+            }
+        }
+    }
+
+    fn on_event(
+        &self,
+        event: &Event<'_>,
+        ctx: Context<'_, S>,
+    ) {
+        let scope = match ctx.event_scope(event) {
+            Some(scope) => scope,
+            None => return,
+        };
+        for span in scope {
+            if let Some(metrics) = span.extensions().get::<SpanMetrics>() {
+                event.record(&mut self.fields(&mut lock(&metrics.logger)));
+                return;
+            }
+        }
+    }
+
+    fn on_close(
+        &self,
+        id: Id,
+        ctx: Context<'_, S>,
+    ) {
+        let span = match ctx.span(&id) {
+            Some(span) => span,
+            None => return,
+        };
+        let metrics = match span.extensions_mut().remove::<SpanMetrics>() {
+            Some(metrics) => metrics,
+            None => return,
+        };
+        let mut logger = metrics
+            .logger
+            .into_inner()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let elapsed = self.clock.now().saturating_sub(metrics.started);
+        let _ = logger.put_metric(
+            format!("{}{}", span.name(), DURATION_SUFFIX),
+            elapsed.as_secs_f64() * 1_000.0,
+            Unit::Milliseconds,
+        );
+        let _ = logger.flush();
+    }
+}
+
+fn lock(logger: &Mutex<MetricLogger>) -> std::sync::MutexGuard<'_, MetricLogger> {
+    logger
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assert_metric, clock::ManualClock, testing::MemorySink};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn layer_records_marked_spans() {
+        let sink = MemorySink::default();
+        let clock = ManualClock::default();
+        let layer = EmfLayer::with_sink(sink.clone()).with_clock(clock.clone());
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            let request = tracing::info_span!("Request", emf = "MyApp", tenant = "acme");
+            let _request = request.enter();
+            tracing::info!(
+                metric.items = 3,
+                metric.cache = "warm",
+                outcome = "ok",
+                "processed"
+            );
+            {
+                let lookup = tracing::info_span!("Lookup", emf = true, metric.rows = 2);
+                let _lookup = lookup.enter();
+                clock.advance(Duration::from_millis(5));
+            }
+            let unmarked = tracing::info_span!("Unmarked");
+            let _unmarked = unmarked.enter();
+            tracing::info!(metric.retries = 1);
+        });
+
+        let contexts = sink.contexts();
+        assert_eq!(contexts.len(), 2);
+        let (lookup, request) = (&contexts[0], &contexts[1]);
+        assert_eq!(lookup.namespace, "MyApp");
+        assert_eq!(lookup.properties["tenant"], "acme");
+        assert!(lookup.metrics.contains_key("rows"));
+        assert_eq!(sink.values("Lookup.Duration"), vec![5.0]);
+        assert_eq!(request.namespace, "MyApp");
+        assert_eq!(request.properties["outcome"], "ok");
+        assert!(!request.properties.contains_key("message"));
+        assert!(request.metrics.contains_key("items"));
+        assert_eq!(request.properties["metric.cache"], "warm");
+        assert!(!request.metrics.contains_key("cache"));
+        assert!(request.metrics.contains_key("retries"));
+        assert_metric!(sink, "Request.Duration", Unit::Milliseconds);
+    }
+}
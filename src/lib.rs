@@ -18,13 +18,45 @@
 // only pub for benches
 #[doc(hidden)]
 pub mod log;
-pub use log::{metric_scope, MetricLogger, Unit};
+pub use log::{
+    metric_scope, try_metric_scope, Aggregation, CollisionPolicy, MetricContext, MetricLogger,
+    OutcomeMetrics, Unit,
+};
+mod cardinality;
+pub use cardinality::CardinalityGuard;
+mod clock;
+pub use clock::{Clock, FixedClock, ManualClock, SystemClock};
 mod config;
+pub mod emf;
 mod env;
+mod error;
+pub use error::Error;
+#[cfg(feature = "tracing")]
+mod layer;
+#[cfg(feature = "tracing")]
+pub use layer::EmfLayer;
+#[cfg(feature = "metrics")]
+mod recorder;
+#[cfg(feature = "metrics")]
+pub use recorder::EmfRecorder;
+mod registry;
+pub use registry::{MetricsRegistry, RegistryScope};
 // only pub for benches
 #[doc(hidden)]
 pub mod serialize;
+pub use serialize::{Deterministic, Serialize};
+mod shared;
+pub use shared::SharedMetricLogger;
 mod sink;
+pub use sink::Sink;
+mod sketch;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub use sketch::Sketch;
+mod timer;
+pub use timer::{Timed, Timer};
+mod validate;
+pub use validate::{Normalization, Sanitization};
 
 #[macro_export]
 macro_rules! dimensions {
@@ -1,13 +1,33 @@
 use crate::{
+    cardinality::{CardinalityGuard, CARDINALITY_EXCEEDED_METRIC},
+    clock::{Clock, SystemClock},
     dimensions,
-    env::{Detector, EnvironmentProvider},
+    env::{Detector, Environment, EnvironmentProvider},
+    error::{self, Error},
+    sink::Sink,
+    sketch::{Sketch, DEFAULT_RELATIVE_ACCURACY},
+    timer::{Timed, Timer},
+    validate::{
+        self, Normalization, Sanitization, MAX_DIMENSION_NAME_LEN, MAX_DIMENSION_VALUE_LEN,
+    },
 };
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::{collections::HashMap, time::UNIX_EPOCH};
+use std::{
+    any,
+    collections::HashMap,
+    convert::{TryFrom, TryInto},
+    future::Future,
+    mem,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Mutex},
+};
 
 const DEFAULT_NAMEPSACE: &str = "aws-embedded-metrics";
 
+/// Root member of an emitted document which holds its metadata
+const RESERVED_KEY: &str = "_aws";
+
 /// Central api for logging acquiring metric logger
 ///
 /// You can capture up to 100 metrics at a time
@@ -30,8 +50,110 @@ pub fn metric_scope<T>(mut f: impl FnMut(&mut MetricLogger) -> T) -> T {
     f(&mut MetricLogger::default())
 }
 
+/// Like `metric_scope`, but for fallible work, recording its outcome
+///
+/// `Success` and `Failure` count metrics are recorded, set to 1 or 0, along with an
+/// `ErrorType` property naming the error type on failure. Use `OutcomeMetrics`
+/// to choose other names. Metrics are flushed even if the closure panics, in which
+/// case a failure is recorded with an `ErrorType` of `panic`
+///
+/// # example
+/// ```rust,edition2018
+/// use aws_embedded_metrics::{try_metric_scope, Unit};
+///
+/// # fn main() {
+/// let parsed: Result<u32, std::num::ParseIntError> = try_metric_scope(|metrics| {
+///     metrics.put_metric("InputLength", 3, Unit::Count);
+///     "123".parse()
+/// });
+/// # }
+/// ```
+pub fn try_metric_scope<T, E>(f: impl FnOnce(&mut MetricLogger) -> Result<T, E>) -> Result<T, E> {
+    OutcomeMetrics::default().run(MetricLogger::default(), f)
+}
+
+/// Names of the members `try_metric_scope` records the outcome of work with
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutcomeMetrics {
+    success: String,
+    failure: String,
+    error_type: String,
+}
+
+impl Default for OutcomeMetrics {
+    fn default() -> Self {
+        OutcomeMetrics::new("Success", "Failure")
+    }
+}
+
+impl OutcomeMetrics {
+    /// Creates outcome metrics with the given success and failure count metric names
+    pub fn new(
+        success: impl Into<String>,
+        failure: impl Into<String>,
+    ) -> Self {
+        OutcomeMetrics {
+            success: success.into(),
+            failure: failure.into(),
+            error_type: "ErrorType".into(),
+        }
+    }
+
+    /// Sets the name of the property recording the type of error on failure
+    pub fn with_error_type_property(
+        mut self,
+        name: impl Into<String>,
+    ) -> Self {
+        self.error_type = name.into();
+        self
+    }
+
+    fn record(
+        &self,
+        logger: &mut MetricLogger,
+        error_type: Option<&str>,
+    ) {
+        let failed = error_type.is_some();
+        let _ = logger.increment(
+            self.success.as_str(),
+            if failed { 0 } else { 1 },
+            Unit::Count,
+        );
+        let _ = logger.increment(
+            self.failure.as_str(),
+            if failed { 1 } else { 0 },
+            Unit::Count,
+        );
+        if let Some(error_type) = error_type {
+            let _ = logger.set_property(self.error_type.as_str(), error_type);
+        }
+    }
+
+    /// Runs fallible work with the given logger, recording its outcome and then
+    /// flushing the logger, even when the work panics
+    pub fn run<T, E>(
+        &self,
+        mut logger: MetricLogger,
+        f: impl FnOnce(&mut MetricLogger) -> Result<T, E>,
+    ) -> Result<T, E> {
+        match panic::catch_unwind(AssertUnwindSafe(|| f(&mut logger))) {
+            Ok(result) => {
+                let error_type = result.as_ref().err().map(|_| any::type_name::<E>());
+                self.record(&mut logger, error_type);
+                let _ = logger.flush();
+                result
+            }
+            Err(payload) => {
+                self.record(&mut logger, Some("panic"));
+                let _ = logger.flush();
+                panic::resume_unwind(payload)
+            }
+        }
+    }
+}
+
 /// Metric unit types
-#[derive(Serialize, Debug, Copy, Clone)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Default)]
 pub enum Unit {
     Seconds,
     Microseconds,
@@ -70,40 +192,373 @@ pub enum Unit {
     TerabitsPerSecond,
     #[serde(rename = "Count/Second")]
     CountPerSecond,
+    #[default]
     None,
 }
 
-impl Default for Unit {
-    fn default() -> Unit {
-        Unit::None
+/// Strategies for accumulating the values recorded for a single metric
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum Aggregation {
+    /// Every recorded value is retained and emitted as is
+    #[default]
+    Raw,
+    /// Identical values are collapsed into value/count pairs
+    /// and emitted in the compact `{"Values": [...], "Counts": [...]}` form
+    Compressed,
+    /// Only the minimum, maximum, sum and sample count of recorded values are retained
+    /// and emitted as an EMF statistic set
+    StatisticSet,
+    /// Values are recorded in a sketch with the given relative accuracy and emitted
+    /// in the `{"Values": [...], "Counts": [...]}` form, from which CloudWatch
+    /// can compute percentiles
+    Histogram { relative_accuracy: f64 },
+    /// Values are summed into a single value, as for a counter
+    Sum,
+    /// Only the last recorded value is retained, as for a gauge
+    Last,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum Values {
+    Raw(Vec<f64>),
+    Compressed {
+        values: Vec<f64>,
+        counts: Vec<u64>,
+    },
+    StatisticSet {
+        min: f64,
+        max: f64,
+        sum: f64,
+        count: u64,
+    },
+    Histogram(Sketch),
+    Sum(f64),
+    Last(f64),
+}
+
+impl TryFrom<Aggregation> for Values {
+    type Error = Error;
+    fn try_from(aggregation: Aggregation) -> Result<Values, Error> {
+        Ok(match aggregation {
+            Aggregation::Raw => Values::Raw(Vec::new()),
+            Aggregation::Compressed => Values::Compressed {
+                values: Vec::new(),
+                counts: Vec::new(),
+            },
+            Aggregation::StatisticSet => Values::StatisticSet {
+                min: f64::INFINITY,
+                max: f64::NEG_INFINITY,
+                sum: 0.0,
+                count: 0,
+            },
+            Aggregation::Histogram { relative_accuracy } => {
+                Values::Histogram(Sketch::new(relative_accuracy)?)
+            }
+            Aggregation::Sum => Values::Sum(0.0),
+            Aggregation::Last => Values::Last(0.0),
+        })
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) struct MetricValues {
-    pub(crate) values: Vec<f64>,
+    pub(crate) values: Values,
     pub(crate) unit: Unit,
 }
 
 impl MetricValues {
+    pub(crate) fn new(
+        unit: Unit,
+        aggregation: Aggregation,
+    ) -> Result<Self, Error> {
+        Ok(MetricValues {
+            values: aggregation.try_into()?,
+            unit,
+        })
+    }
+
+    /// Creates values holding a single value, failing when either is invalid
+    pub(crate) fn with_value(
+        unit: Unit,
+        aggregation: Aggregation,
+        value: f64,
+    ) -> Result<Self, Error> {
+        let mut values = MetricValues::new(unit, aggregation)?;
+        values.add(value)?;
+        Ok(values)
+    }
+
+    /// Records a value, failing when it is NaN or infinite since CloudWatch can't store it
     pub fn add(
         &mut self,
         value: f64,
-    ) {
-        self.values.push(value)
+    ) -> Result<(), Error> {
+        if !value.is_finite() {
+            return Err(Error::NonFiniteValue(value));
+        }
+        match &mut self.values {
+            Values::Raw(values) => values.push(value),
+            Values::Compressed { values, counts } => {
+                match values.iter().position(|v| v.to_bits() == value.to_bits()) {
+                    Some(idx) => counts[idx] += 1,
+                    None => {
+                        values.push(value);
+                        counts.push(1);
+                    }
+                }
+            }
+            Values::StatisticSet {
+                min,
+                max,
+                sum,
+                count,
+            } => {
+                *min = min.min(value);
+                *max = max.max(value);
+                *sum += value;
+                *count += 1;
+            }
+            Values::Histogram(sketch) => sketch.add(value)?,
+            Values::Sum(sum) => *sum += value,
+            Values::Last(last) => *last = value,
+        }
+        Ok(())
     }
 }
 
-#[derive(Debug)]
+/// How a context handles a property, dimension or metric whose name is already in use
+/// by a member of another kind. All of these are flattened into the root of the emitted
+/// document, which can hold only one value per name
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum CollisionPolicy {
+    /// The colliding member is ignored and an error recorded
+    #[default]
+    Reject,
+    /// The colliding member is recorded anyway and an error recorded.
+    /// Only one of the colliding values will be emitted
+    Allow,
+}
+
+/// Kinds of members flattened into the root of an emitted document
+#[derive(Clone, Copy, PartialEq)]
+enum Member<'a> {
+    Property,
+    Dimension(&'a str),
+    Metric,
+}
+
+/// An additional group of metrics published under its own namespace and dimensions
+#[derive(Debug, Clone)]
+pub(crate) struct Directive {
+    /// When absent, metrics are published under the context's namespace
+    pub(crate) namespace: Option<String>,
+    pub(crate) dimensions: Vec<HashMap<String, String>>,
+    pub(crate) metrics: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
 pub struct MetricContext {
     pub(crate) namespace: String,
     pub(crate) meta: HashMap<String, Value>,
     pub(crate) properties: HashMap<String, Value>,
     pub(crate) dimensions: Vec<HashMap<String, String>>,
     pub(crate) metrics: HashMap<String, MetricValues>,
+    pub(crate) aggregation: Aggregation,
+    pub(crate) directives: Vec<Directive>,
+    pub(crate) collision_policy: CollisionPolicy,
+    pub(crate) dimension_sanitization: Sanitization,
+    pub(crate) cardinality_guard: Option<Arc<CardinalityGuard>>,
+    pub(crate) metric_name_normalization: Normalization,
+    pub(crate) clock: Arc<dyn Clock>,
+    pub(crate) errors: Vec<Error>,
 }
 
 impl MetricContext {
+    /// Creates a new context sharing this context's namespace, properties, dimensions
+    /// and aggregation but none of its recorded metrics
+    pub(crate) fn fork(&self) -> MetricContext {
+        let mut forked = MetricContext {
+            namespace: self.namespace.clone(),
+            properties: self.properties.clone(),
+            dimensions: self.dimensions.clone(),
+            aggregation: self.aggregation,
+            directives: self.directives.clone(),
+            collision_policy: self.collision_policy,
+            dimension_sanitization: self.dimension_sanitization,
+            cardinality_guard: self.cardinality_guard.clone(),
+            metric_name_normalization: self.metric_name_normalization,
+            ..MetricContext::default()
+        };
+        forked.set_shared_clock(self.clock.clone());
+        forked
+    }
+
+    /// Errors recorded since this context was created
+    pub fn errors(&self) -> &[Error] {
+        &self.errors
+    }
+
+    /// Sets how names colliding with members of another kind are handled
+    pub fn set_collision_policy(
+        &mut self,
+        policy: CollisionPolicy,
+    ) {
+        self.collision_policy = policy;
+    }
+
+    /// Sets how invalid dimension names and values are made valid.
+    /// By default they are rejected
+    pub fn set_dimension_sanitization(
+        &mut self,
+        sanitization: Sanitization,
+    ) {
+        self.dimension_sanitization = sanitization;
+    }
+
+    /// Limits the distinct values recorded for each dimension name
+    pub fn set_cardinality_guard(
+        &mut self,
+        guard: Arc<CardinalityGuard>,
+    ) {
+        self.cardinality_guard = Some(guard);
+    }
+
+    /// Sets how metric names are normalized before they are validated.
+    /// By default names are only validated
+    pub fn set_metric_name_normalization(
+        &mut self,
+        normalization: Normalization,
+    ) {
+        self.metric_name_normalization = normalization;
+    }
+
+    /// Sets the clock which timestamps this context, restamping it with the clock's time
+    pub fn set_clock(
+        &mut self,
+        clock: impl Clock + 'static,
+    ) {
+        self.set_shared_clock(Arc::new(clock));
+    }
+
+    pub(crate) fn set_shared_clock(
+        &mut self,
+        clock: Arc<dyn Clock>,
+    ) {
+        self.meta
+            .insert("Timestamp".into(), clock.now_millis().into());
+        self.clock = clock;
+    }
+
+    /// Normalizes and validates a metric name, recording an error when it is invalid
+    fn metric_name(
+        &mut self,
+        name: String,
+    ) -> Option<String> {
+        match validate::metric_name(&name, self.metric_name_normalization) {
+            Ok(normalized) => Some(normalized.into_owned()),
+            Err(reason) => {
+                self.errors.push(Error::InvalidMetricName { name, reason });
+                None
+            }
+        }
+    }
+
+    fn dimension_value(
+        &self,
+        key: &str,
+    ) -> Option<&str> {
+        self.dimensions
+            .iter()
+            .chain(
+                self.directives
+                    .iter()
+                    .flat_map(|directive| directive.dimensions.iter()),
+            )
+            .find_map(|dims| dims.get(key))
+            .map(String::as_str)
+    }
+
+    /// Returns true when `name` may be recorded as the given member,
+    /// recording an error when it is reserved or collides with another member
+    fn admit(
+        &mut self,
+        name: &str,
+        member: Member<'_>,
+    ) -> bool {
+        if name == RESERVED_KEY {
+            self.errors.push(Error::ReservedKey(name.into()));
+            return false;
+        }
+        let collides = (member != Member::Property && self.properties.contains_key(name))
+            || (member != Member::Metric && self.metrics.contains_key(name))
+            || match (self.dimension_value(name), member) {
+                (Some(existing), Member::Dimension(value)) => existing != value,
+                (Some(_), _) => true,
+                _ => false,
+            };
+        if !collides {
+            return true;
+        }
+        self.errors.push(Error::KeyCollision(name.into()));
+        self.collision_policy == CollisionPolicy::Allow
+    }
+
+    /// Validates a dimension set, dropping the whole set when any dimension is invalid
+    /// or collides with another member, since publishing the rest would aggregate
+    /// the metrics under a different dimension set than the one asked for
+    fn admit_dimensions(
+        &mut self,
+        dims: HashMap<String, String>,
+    ) -> Option<HashMap<String, String>> {
+        let sanitization = self.dimension_sanitization;
+        let guard = self.cardinality_guard.clone();
+        let mut admitted = HashMap::with_capacity(dims.len());
+        let mut replaced = Vec::new();
+        let mut rejected = false;
+        for (key, value) in dims {
+            let valid =
+                validate::dimension(&key, MAX_DIMENSION_NAME_LEN, sanitization).and_then(|name| {
+                    validate::dimension(&value, MAX_DIMENSION_VALUE_LEN, sanitization)
+                        .map(|value| (name.into_owned(), value.into_owned()))
+                });
+            match valid {
+                Ok((key, value)) => {
+                    let (value, was_replaced) = match &guard {
+                        Some(guard) => guard.peek(&key, value, &*self.clock),
+                        None => (value, false),
+                    };
+                    if !self.admit(&key, Member::Dimension(&value)) {
+                        rejected = true;
+                        continue;
+                    }
+                    if was_replaced {
+                        replaced.push(key.clone());
+                    }
+                    admitted.insert(key, value);
+                }
+                Err(reason) => {
+                    self.errors
+                        .push(Error::InvalidDimension { name: key, reason });
+                    rejected = true;
+                }
+            }
+        }
+        if rejected {
+            return None;
+        }
+        // values are only counted against the guard's limit once their set is admitted
+        if let Some(guard) = &guard {
+            for (key, value) in &admitted {
+                if replaced.contains(key) {
+                    let _ = self.increment(CARDINALITY_EXCEEDED_METRIC, 1, Unit::Count);
+                } else {
+                    guard.admit(key, value.clone(), &*self.clock);
+                }
+            }
+        }
+        Some(admitted)
+    }
+
     pub fn set_namespace(
         &mut self,
         namespace: impl Into<String>,
@@ -111,19 +566,76 @@ impl MetricContext {
         self.namespace = namespace.into()
     }
 
+    /// Returns the first error recorded since `recorded` errors had been
+    fn rejection(
+        &self,
+        recorded: usize,
+    ) -> Result<(), Error> {
+        match self.errors.get(recorded) {
+            Some(err) => Err(err.clone()),
+            None => Ok(()),
+        }
+    }
+
     pub fn set_property(
         &mut self,
         name: impl Into<String>,
         value: impl Into<Value>,
-    ) {
-        self.properties.insert(name.into(), value.into());
+    ) -> Result<(), Error> {
+        let recorded = self.errors.len();
+        let (name, value) = (name.into(), value.into());
+        if let Err(reason) = validate::property_value(&value) {
+            self.errors.push(Error::InvalidProperty { name, reason });
+        } else if self.admit(&name, Member::Property) {
+            self.properties.insert(name, value);
+        }
+        self.rejection(recorded)
     }
 
     pub fn put_dimensions(
         &mut self,
         dims: HashMap<String, String>,
+    ) -> Result<(), Error> {
+        let recorded = self.errors.len();
+        if let Some(dims) = self.admit_dimensions(dims) {
+            self.dimensions.push(dims);
+        }
+        self.rejection(recorded)
+    }
+
+    /// Publishes the named metrics under an additional namespace and dimension sets.
+    ///
+    /// Metrics referenced by a directive are no longer published under this
+    /// context's own namespace and dimensions
+    pub fn put_directive(
+        &mut self,
+        namespace: impl Into<String>,
+        dimensions: Vec<HashMap<String, String>>,
+        metrics: Vec<String>,
+    ) -> Result<(), Error> {
+        let recorded = self.errors.len();
+        let dimensions = dimensions
+            .into_iter()
+            .filter_map(|dims| self.admit_dimensions(dims))
+            .collect();
+        let metrics = metrics
+            .into_iter()
+            .filter_map(|name| self.metric_name(name))
+            .collect();
+        self.directives.push(Directive {
+            namespace: Some(namespace.into()),
+            dimensions,
+            metrics,
+        });
+        self.rejection(recorded)
+    }
+
+    /// Sets the aggregation used for metrics first recorded after this call
+    pub fn set_aggregation(
+        &mut self,
+        aggregation: Aggregation,
     ) {
-        self.dimensions.push(dims);
+        self.aggregation = aggregation;
     }
 
     pub fn put_metric(
@@ -131,14 +643,160 @@ impl MetricContext {
         name: impl Into<String>,
         value: impl Into<f64>,
         unit: Unit,
-    ) {
-        self.metrics
-            .entry(name.into())
-            .or_insert_with(|| MetricValues {
-                values: Vec::new(),
-                unit,
+    ) -> Result<(), Error> {
+        let aggregation = self.aggregation;
+        self.put_metric_aggregated(name, value, unit, aggregation)
+    }
+
+    /// Records a metric value using an aggregation specific to this metric.
+    ///
+    /// The aggregation is fixed by the first value recorded for a metric name
+    pub fn put_metric_aggregated(
+        &mut self,
+        name: impl Into<String>,
+        value: impl Into<f64>,
+        unit: Unit,
+        aggregation: Aggregation,
+    ) -> Result<(), Error> {
+        let recorded = self.errors.len();
+        if let Some(name) = self.metric_name(name.into()) {
+            let value = value.into();
+            let added = if let Some(metric) = self.metrics.get_mut(&name) {
+                metric.add(value)
+            } else if self.admit(&name, Member::Metric) {
+                MetricValues::with_value(unit, aggregation, value).map(|metric| {
+                    self.metrics.insert(name, metric);
+                })
+            } else {
+                Ok(())
+            };
+            if let Err(err) = added {
+                self.errors.push(err);
+            }
+        }
+        self.rejection(recorded)
+    }
+
+    /// Records a metric value published only with the given dimensions rather than the
+    /// context's dimension sets. Metrics sharing the same dimensions are grouped
+    /// into a single directive. Values whose dimensions collide with another member,
+    /// such as the same dimension with a different value, or which differ from the
+    /// dimensions the metric was first recorded with are dropped
+    pub fn put_metric_with_dimensions(
+        &mut self,
+        name: impl Into<String>,
+        value: impl Into<f64>,
+        unit: Unit,
+        dimensions: HashMap<String, String>,
+    ) -> Result<(), Error> {
+        let recorded = self.errors.len();
+        let name = match self.metric_name(name.into()) {
+            Some(name) => name,
+            None => return self.rejection(recorded),
+        };
+        let dimensions = match self.admit_dimensions(dimensions) {
+            Some(dimensions) => dimensions,
+            None => {
+                // a document holds one value per dimension name, so a metric whose
+                // dimension collided can't be published with the dimensions it was
+                // recorded with
+                let collision = self.errors[recorded..].iter().find_map(|err| match err {
+                    Error::KeyCollision(name) => Some(name.clone()),
+                    _ => None,
+                });
+                if let Some(dimension) = collision {
+                    self.errors.push(Error::ConflictingDimension {
+                        metric: name,
+                        dimension,
+                    });
+                }
+                return self.rejection(recorded);
+            }
+        };
+        // values are pooled per metric name, so a metric can only be published
+        // with the dimension set it was first recorded with
+        let conflict = self
+            .directives
+            .iter()
+            .find(|directive| {
+                directive.namespace.is_none()
+                    && directive.dimensions.len() == 1
+                    && directive.metrics.contains(&name)
             })
-            .add(value.into());
+            .and_then(|directive| {
+                let recorded = &directive.dimensions[0];
+                dimensions
+                    .iter()
+                    .filter(|(key, value)| recorded.get(*key) != Some(*value))
+                    .map(|(key, _)| key)
+                    .chain(recorded.keys().filter(|key| !dimensions.contains_key(*key)))
+                    .min()
+                    .cloned()
+            });
+        if let Some(dimension) = conflict {
+            self.errors.push(Error::ConflictingDimension {
+                metric: name,
+                dimension,
+            });
+            return self.rejection(recorded);
+        }
+        // directives may only reference metrics which hold a value
+        self.put_metric(name.clone(), value, unit)?;
+        let existing = self.directives.iter_mut().find(|directive| {
+            directive.namespace.is_none()
+                && directive.dimensions.len() == 1
+                && directive.dimensions[0] == dimensions
+        });
+        match existing {
+            Some(directive) => {
+                if !directive.metrics.contains(&name) {
+                    directive.metrics.push(name.clone());
+                }
+            }
+            None => self.directives.push(Directive {
+                namespace: None,
+                dimensions: vec![dimensions],
+                metrics: vec![name],
+            }),
+        }
+        self.rejection(recorded)
+    }
+
+    /// Records a metric value in a histogram with the default relative accuracy of 1%
+    pub fn put_histogram(
+        &mut self,
+        name: impl Into<String>,
+        value: impl Into<f64>,
+        unit: Unit,
+    ) -> Result<(), Error> {
+        self.put_metric_aggregated(
+            name,
+            value,
+            unit,
+            Aggregation::Histogram {
+                relative_accuracy: DEFAULT_RELATIVE_ACCURACY,
+            },
+        )
+    }
+
+    /// Adds to a metric emitted as the sum of all increments
+    pub fn increment(
+        &mut self,
+        name: impl Into<String>,
+        by: impl Into<f64>,
+        unit: Unit,
+    ) -> Result<(), Error> {
+        self.put_metric_aggregated(name, by, unit, Aggregation::Sum)
+    }
+
+    /// Sets a metric emitted as the last value set
+    pub fn gauge(
+        &mut self,
+        name: impl Into<String>,
+        value: impl Into<f64>,
+        unit: Unit,
+    ) -> Result<(), Error> {
+        self.put_metric_aggregated(name, value, unit, Aggregation::Last)
     }
 }
 
@@ -147,11 +805,19 @@ impl Default for MetricContext {
         MetricContext {
             namespace: DEFAULT_NAMEPSACE.into(),
             meta: dimensions!(
-                "Timestamp" => UNIX_EPOCH.elapsed().unwrap_or_default().as_millis() as u64
+                "Timestamp" => SystemClock.now_millis()
             ),
             properties: HashMap::default(),
             dimensions: Vec::new(),
             metrics: HashMap::default(),
+            aggregation: Aggregation::default(),
+            directives: Vec::new(),
+            collision_policy: CollisionPolicy::default(),
+            dimension_sanitization: Sanitization::default(),
+            cardinality_guard: None,
+            metric_name_normalization: Normalization::default(),
+            clock: Arc::new(SystemClock),
+            errors: Vec::new(),
         }
     }
 }
@@ -162,12 +828,16 @@ impl Default for MetricContext {
 /// You may customize this for your application with the `set_namespace` function
 pub struct MetricLogger {
     context: MetricContext,
-    get_env: Box<dyn EnvironmentProvider>,
+    /// Absent for loggers given their own sink, which never detect the environment
+    get_env: Option<Box<dyn EnvironmentProvider>>,
+    /// Shared with child loggers, which flush to the same sink
+    sink: Option<Arc<Mutex<dyn Sink>>>,
 }
 
 impl Drop for MetricLogger {
     fn drop(&mut self) {
-        self.flush()
+        // errors are written to stderr by flush when debug logging is enabled
+        let _ = self.flush();
     }
 }
 
@@ -175,19 +845,89 @@ impl Default for MetricLogger {
     fn default() -> MetricLogger {
         MetricLogger {
             context: MetricContext::default(),
-            get_env: Box::new(Detector),
+            get_env: Some(Box::new(Detector)),
+            sink: None,
         }
     }
 }
 
 impl MetricLogger {
+    /// Creates a logger which flushes to the given sink.
+    ///
+    /// Unlike the default logger, the environment is not detected, so no default
+    /// dimensions or environment properties are added on flush
+    pub fn with_sink(sink: impl Sink + 'static) -> MetricLogger {
+        MetricLogger {
+            context: MetricContext::default(),
+            get_env: None,
+            sink: Some(Arc::new(Mutex::new(sink))),
+        }
+    }
+
+    /// Creates a logger which starts with this logger's namespace, dimensions, properties
+    /// and configuration but none of its metrics.
+    ///
+    /// Child loggers are flushed independently of their parent, to the same sink. Use them
+    /// for sub-operations which should share request-level context like a tenant or route
+    ///
+    /// # example
+    /// ```rust,edition2018
+    /// use aws_embedded_metrics::{dimensions, metric_scope, Unit};
+    ///
+    /// # fn main() {
+    /// metric_scope(|metrics| {
+    ///     metrics.put_dimensions(dimensions! { "Tenant" => "acme" });
+    ///     let mut lookup = metrics.child();
+    ///     lookup.put_metric("LookupLatency", 3, Unit::Milliseconds);
+    /// });
+    /// # }
+    /// ```
+    pub fn child(&self) -> MetricLogger {
+        MetricLogger {
+            context: self.context.fork(),
+            get_env: self
+                .sink
+                .is_none()
+                .then(|| Box::new(Detector) as Box<dyn EnvironmentProvider>),
+            sink: self.sink.clone(),
+        }
+    }
+
     /// Flushes the current context state to the configured sink.
     ///
-    /// Then `MetricLogger` values are dropped, `flush` is called for you
-    pub fn flush(&mut self) {
-        let _ = self.get_env.get();
-        // todo: syncs
-        println!("metrics logger was flushed");
+    /// Returns the errors recorded since the last flush along with any raised while
+    /// serializing or sending the metrics. These are also written to stderr when
+    /// `AWS_EMF_ENABLE_DEBUG_LOGGING` is set.
+    ///
+    /// When `MetricLogger` values are dropped, `flush` is called for you
+    pub fn flush(&mut self) -> Result<(), Vec<Error>> {
+        if self.context.metrics.is_empty() {
+            return Ok(());
+        }
+        let sink = match (&self.sink, &mut self.get_env) {
+            (Some(sink), _) => sink.clone(),
+            (None, Some(get_env)) => {
+                let Environment { env, sink } = get_env.get();
+                if self.context.dimensions.is_empty() {
+                    let _ = self.context.put_dimensions(dimensions! {
+                        "LogGroup" => env.log_group_name(),
+                        "ServiceName" => env.name(),
+                        "ServiceType" => env.env_type()
+                    });
+                }
+                env.configure(&mut self.context);
+                sink
+            }
+            (None, None) => return Ok(()),
+        };
+        let mut errors = mem::take(&mut self.context.errors);
+        let next = self.context.fork();
+        let accepted = sink
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .accept(mem::replace(&mut self.context, next));
+        errors.extend(accepted.err().into_iter().flatten());
+        error::report(errors)
     }
 
     /// Set the CloudWatch namespace that metrics should be published to.
@@ -198,6 +938,102 @@ impl MetricLogger {
         self.context.set_namespace(ns);
     }
 
+    /// Publish the named metrics to an additional namespace with their own dimension sets.
+    ///
+    /// This lets a single log event target several namespaces, or give a group of
+    /// metrics dimensions that differ from the rest. Metrics named here are published
+    /// only under this directive, not the logger's namespace and dimensions
+    pub fn put_directive(
+        &mut self,
+        namespace: impl Into<String>,
+        dimensions: Vec<HashMap<String, String>>,
+        metrics: Vec<String>,
+    ) -> Result<(), Error> {
+        self.context.put_directive(namespace, dimensions, metrics)
+    }
+
+    /// Errors recorded since the last flush, such as names which collided with
+    /// one another. Each setter also returns the first error it records.
+    /// Remaining errors are returned by `flush`
+    pub fn errors(&self) -> &[Error] {
+        self.context.errors()
+    }
+
+    /// Set how properties, dimensions and metrics whose names collide with one another are handled.
+    ///
+    /// Properties, dimension values and metric values all share the top level of the emitted
+    /// document, so a name may only be used by one of them. By default colliding names are
+    /// rejected. The name `_aws` is reserved for metadata and always rejected
+    pub fn set_collision_policy(
+        &mut self,
+        policy: CollisionPolicy,
+    ) {
+        self.context.set_collision_policy(policy);
+    }
+
+    /// Set how invalid dimension names and values are handled.
+    ///
+    /// CloudWatch requires dimension names and values to be non-empty ASCII, without a
+    /// leading colon, and at most 255 and 1024 characters long respectively. By default
+    /// dimension sets holding a dimension which breaks these rules are dropped and
+    /// reported in `errors`.
+    /// A `Sanitization` may instead truncate them or replace invalid characters
+    pub fn set_dimension_sanitization(
+        &mut self,
+        sanitization: Sanitization,
+    ) {
+        self.context.set_dimension_sanitization(sanitization);
+    }
+
+    /// Guard dimensions against unbounded cardinality.
+    ///
+    /// CloudWatch treats every distinct combination of dimension values as a separate
+    /// metric, so a dimension with values like request ids quickly becomes expensive.
+    /// Once the guard has seen its limit of distinct values for a dimension name, further
+    /// values are replaced and a `DimensionCardinalityExceeded` metric is recorded.
+    /// Share one guard between loggers so it observes values across units of work
+    pub fn set_cardinality_guard(
+        &mut self,
+        guard: Arc<CardinalityGuard>,
+    ) {
+        self.context.set_cardinality_guard(guard);
+    }
+
+    /// Set how metric names are normalized.
+    ///
+    /// Metric names must be non-empty and at most 1024 characters long. By default
+    /// metrics with invalid names are dropped and reported in `errors`. A `Normalization`
+    /// may trim names, replace whitespace within them and truncate them first
+    pub fn set_metric_name_normalization(
+        &mut self,
+        normalization: Normalization,
+    ) {
+        self.context.set_metric_name_normalization(normalization);
+    }
+
+    /// Set the clock used to timestamp emitted documents.
+    ///
+    /// Documents are timestamped when the logger is created and after each flush.
+    /// A `FixedClock` or `ManualClock` makes timestamps predictable in tests
+    pub fn set_clock(
+        &mut self,
+        clock: impl Clock + 'static,
+    ) {
+        self.context.set_clock(clock);
+    }
+
+    /// Set the aggregation strategy for metrics recorded from here on.
+    ///
+    /// By default every value is retained. `Aggregation::Compressed` collapses
+    /// repeated values into value/count pairs, which keeps payloads small for
+    /// metrics recorded in hot loops
+    pub fn set_aggregation(
+        &mut self,
+        aggregation: Aggregation,
+    ) {
+        self.context.set_aggregation(aggregation);
+    }
+
     /// Set an aribtrary property on the published metrics.
     /// This is stored in the emitted log data and you are not
     /// charged for this data by CloudWatch Metrics.
@@ -209,8 +1045,8 @@ impl MetricLogger {
         &mut self,
         name: impl Into<String>,
         value: impl Into<Value>,
-    ) {
-        self.context.set_property(name, value);
+    ) -> Result<(), Error> {
+        self.context.set_property(name, value)
     }
 
     /// Adds a dimension.
@@ -221,8 +1057,8 @@ impl MetricLogger {
     pub fn put_dimensions(
         &mut self,
         dims: HashMap<String, String>,
-    ) {
-        self.context.put_dimensions(dims);
+    ) -> Result<(), Error> {
+        self.context.put_dimensions(dims)
     }
 
     /// Put a metric value.
@@ -232,26 +1068,298 @@ impl MetricLogger {
     /// Although the Value parameter accepts floating point numbers,
     /// CloudWatch rejects values that are either too small or too large.
     /// Values must be in the range of -2^360 to 2^360.
-    /// In addition, special values (for example, NaN, +Infinity, -Infinity) are not supported.
+    /// In addition, special values (for example, NaN, +Infinity, -Infinity) are not supported
+    /// and are rejected.
     pub fn put_metric(
         &mut self,
         name: impl Into<String>,
         value: impl Into<f64>,
         unit: Unit,
-    ) {
-        self.context.put_metric(name, value, unit);
+    ) -> Result<(), Error> {
+        self.context.put_metric(name, value, unit)
+    }
+
+    /// Put a metric value, accumulated with the given aggregation rather than
+    /// the logger's default.
+    ///
+    /// `Aggregation::StatisticSet` is well suited for high frequency counters
+    /// where individual samples are not interesting
+    pub fn put_metric_aggregated(
+        &mut self,
+        name: impl Into<String>,
+        value: impl Into<f64>,
+        unit: Unit,
+        aggregation: Aggregation,
+    ) -> Result<(), Error> {
+        self.context
+            .put_metric_aggregated(name, value, unit, aggregation)
+    }
+
+    /// Put a metric value published only with the given dimensions.
+    ///
+    /// Every metric put with `put_metric` is published with every dimension set
+    /// added with `put_dimensions`. Since CloudWatch charges for each unique
+    /// combination, use this for metrics which need a breakdown the others do not.
+    ///
+    /// A log event holds one value per dimension name, so a value whose dimensions
+    /// disagree with ones already recorded, say `Operation` as both "get" and "put",
+    /// is dropped and reported in `errors`. So is a value for a metric already recorded
+    /// with other dimensions. Record those with separate loggers
+    pub fn put_metric_with_dimensions(
+        &mut self,
+        name: impl Into<String>,
+        value: impl Into<f64>,
+        unit: Unit,
+        dimensions: HashMap<String, String>,
+    ) -> Result<(), Error> {
+        self.context
+            .put_metric_with_dimensions(name, value, unit, dimensions)
+    }
+
+    /// Put a metric value into a histogram.
+    ///
+    /// Histograms keep payloads compact no matter how many values are recorded
+    /// while still allowing CloudWatch to compute percentiles like p50, p90 and p99.
+    /// Values are accurate to within 1%. For a different accuracy use
+    /// `put_metric_aggregated` with `Aggregation::Histogram`
+    pub fn put_histogram(
+        &mut self,
+        name: impl Into<String>,
+        value: impl Into<f64>,
+        unit: Unit,
+    ) -> Result<(), Error> {
+        self.context.put_histogram(name, value, unit)
+    }
+
+    /// Add to a counter.
+    ///
+    /// Where calling `put_metric` repeatedly emits every value, increments are summed
+    /// and emitted as a single value
+    pub fn increment(
+        &mut self,
+        name: impl Into<String>,
+        by: impl Into<f64>,
+        unit: Unit,
+    ) -> Result<(), Error> {
+        self.context.increment(name, by, unit)
+    }
+
+    /// Set a gauge.
+    ///
+    /// Only the last value set before the logger is flushed is emitted
+    pub fn gauge(
+        &mut self,
+        name: impl Into<String>,
+        value: impl Into<f64>,
+        unit: Unit,
+    ) -> Result<(), Error> {
+        self.context.gauge(name, value, unit)
+    }
+
+    /// Start timing something, recording the elapsed time as a metric when
+    /// the returned timer is dropped or stopped.
+    ///
+    /// Time is recorded in milliseconds unless the timer is given another unit
+    /// with `Timer::with_unit`, and is read from the logger's clock
+    pub fn start_timer(
+        &mut self,
+        name: impl Into<String>,
+    ) -> Timer<'_> {
+        let clock = self.context.clock.clone();
+        Timer::new(self, name.into(), clock)
+    }
+
+    /// Time how long a closure takes to run, recording it as a metric in milliseconds
+    pub fn time<T>(
+        &mut self,
+        name: impl Into<String>,
+        f: impl FnOnce() -> T,
+    ) -> T {
+        let _timer = self.start_timer(name);
+        f()
+    }
+
+    /// Time how long a future takes to complete, recording it as a metric in milliseconds.
+    ///
+    /// Timing starts when this is called rather than when the future is first polled
+    pub fn time_future<F: Future>(
+        &mut self,
+        name: impl Into<String>,
+        future: F,
+    ) -> Timed<'_, F> {
+        let clock = self.context.clock.clone();
+        Timed::new(self, name.into(), clock, future)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        assert_metric,
+        clock::ManualClock,
+        emf,
+        serialize::{Log, Serialize as _},
+        testing::MemorySink,
+    };
+    use std::time::Duration;
+
+    fn sum(
+        context: &MetricContext,
+        name: &str,
+    ) -> f64 {
+        match context.metrics[name].values {
+            Values::Sum(sum) => sum,
+            ref other => panic!("unexpected values {:?}", other),
+        }
+    }
+
+    #[test]
+    fn outcome_metrics_record_success_and_failure() {
+        let sink = MemorySink::default();
+        let outcomes = OutcomeMetrics::new("Ok", "Err").with_error_type_property("Cause");
+        let ok: Result<u8, String> = outcomes.run(MetricLogger::with_sink(sink.clone()), |_| Ok(1));
+        assert_eq!(ok, Ok(1));
+        let err: Result<u8, std::fmt::Error> =
+            outcomes.run(MetricLogger::with_sink(sink.clone()), |metrics| {
+                metrics.put_metric("Attempts", 1, Unit::Count).unwrap();
+                Err(std::fmt::Error)
+            });
+        assert!(err.is_err());
+
+        let contexts = sink.contexts();
+        assert_eq!(
+            (sum(&contexts[0], "Ok"), sum(&contexts[0], "Err")),
+            (1.0, 0.0)
+        );
+        assert!(!contexts[0].properties.contains_key("Cause"));
+        assert_eq!(
+            (sum(&contexts[1], "Ok"), sum(&contexts[1], "Err")),
+            (0.0, 1.0)
+        );
+        assert_eq!(contexts[1].properties["Cause"], "core::fmt::Error");
+    }
+
+    #[test]
+    fn outcome_metrics_flush_on_panic() {
+        let sink = MemorySink::default();
+        let captured = sink.clone();
+        let result = panic::catch_unwind(move || {
+            OutcomeMetrics::default()
+                .run(MetricLogger::with_sink(captured), |_| -> Result<(), ()> {
+                    panic!("boom")
+                })
+        });
+        assert!(result.is_err());
+
+        let contexts = sink.contexts();
+        assert_eq!(contexts.len(), 1);
+        assert_eq!(sum(&contexts[0], "Failure"), 1.0);
+        assert_eq!(contexts[0].properties["ErrorType"], "panic");
+    }
+
+    #[test]
+    fn context_timestamps_come_from_its_clock() {
+        let clock = ManualClock::new(Duration::from_secs(1));
+        let mut ctx = MetricContext::default();
+        ctx.set_clock(clock.clone());
+        assert_eq!(ctx.meta["Timestamp"], 1000);
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(ctx.fork().meta["Timestamp"], 2000);
+    }
+
+    #[test]
+    fn timers_record_elapsed_time() {
+        let sink = MemorySink::default();
+        let clock = ManualClock::default();
+        let mut metrics = MetricLogger::with_sink(sink.clone());
+        metrics.set_clock(clock.clone());
+        {
+            let mut timer = metrics.start_timer("Outer").with_unit(Unit::Seconds);
+            clock.advance(Duration::from_millis(1500));
+            timer.put_metric("Items", 1, Unit::Count).unwrap();
+        }
+        let elapsed = metrics.start_timer("Stopped").stop();
+        assert_eq!(elapsed, Duration::from_secs(0));
+        let value = metrics.time("Closure", || {
+            clock.advance(Duration::from_millis(20));
+            7
+        });
+        assert_eq!(value, 7);
+        drop(metrics);
+
+        assert_eq!(sink.values("Outer"), vec![1.5]);
+        assert_metric!(sink, "Outer", Unit::Seconds);
+        assert_eq!(sink.values("Items"), vec![1.0]);
+        assert_eq!(sink.values("Stopped"), vec![0.0]);
+        assert_eq!(sink.values("Closure"), vec![20.0]);
+    }
+
+    #[test]
+    fn timed_futures_record_on_completion() {
+        use std::task::{Context, Poll, Wake, Waker};
+
+        struct Noop;
+        impl Wake for Noop {
+            fn wake(self: Arc<Self>) {}
+        }
+
+        let sink = MemorySink::default();
+        let clock = ManualClock::default();
+        let mut metrics = MetricLogger::with_sink(sink.clone());
+        metrics.set_clock(clock.clone());
+        let advance = clock.clone();
+        let mut timed = metrics.time_future("Request", async move {
+            advance.advance(Duration::from_millis(5));
+            "done"
+        });
+        let waker = Waker::from(Arc::new(Noop));
+        assert_eq!(
+            std::pin::Pin::new(&mut timed).poll(&mut Context::from_waker(&waker)),
+            Poll::Ready("done")
+        );
+        drop(timed);
+        drop(metrics);
+
+        assert_metric!(sink, "Request", Unit::Milliseconds);
+        assert_eq!(sink.values("Request"), vec![5.0]);
+    }
+
+    #[test]
+    fn child_loggers_inherit_context_and_flush_independently() {
+        let sink = MemorySink::default();
+        let mut parent = MetricLogger::with_sink(sink.clone());
+        parent.set_namespace("App");
+        parent
+            .put_dimensions(dimensions! { "Tenant" => "acme" })
+            .unwrap();
+        parent.set_property("RequestId", "abc").unwrap();
+        parent.put_metric("Requests", 1, Unit::Count).unwrap();
+        {
+            let mut child = parent.child();
+            child
+                .put_dimensions(dimensions! { "Operation" => "lookup" })
+                .unwrap();
+            child.put_metric("Latency", 3, Unit::Milliseconds).unwrap();
+        }
+        assert_eq!(parent.context.dimensions.len(), 1);
+        drop(parent);
+
+        let contexts = sink.contexts();
+        assert_eq!(contexts.len(), 2);
+        let child = &contexts[0];
+        assert_eq!(child.namespace, "App");
+        assert_eq!(child.dimensions.len(), 2);
+        assert_eq!(child.properties["RequestId"], "abc");
+        assert!(!child.metrics.contains_key("Requests"));
+        assert!(contexts[1].metrics.contains_key("Requests"));
+    }
 
     #[test]
     fn metric_scope_api() {
         assert_eq!(
             metric_scope(|metrics: &mut MetricLogger| {
-                metrics.put_metric("foo", 1, Unit::Count);
+                metrics.put_metric("foo", 1, Unit::Count).unwrap();
                 1
             }),
             1
@@ -263,6 +1371,333 @@ mod tests {
         assert_eq!(MetricContext::default().namespace, DEFAULT_NAMEPSACE)
     }
 
+    #[test]
+    fn compressed_metric_values_count_repeats() {
+        let mut values = MetricValues::new(Unit::Count, Aggregation::Compressed).unwrap();
+        for value in &[1.0, 2.0, 1.0, 1.0] {
+            values.add(*value).unwrap();
+        }
+        match values.values {
+            Values::Compressed { values, counts } => {
+                assert_eq!(values, vec![1.0, 2.0]);
+                assert_eq!(counts, vec![3, 1]);
+            }
+            other => panic!("unexpected values {:?}", other),
+        }
+    }
+
+    #[test]
+    fn invalid_values_and_accuracies_are_rejected() {
+        let mut ctx = MetricContext::default();
+        assert!(matches!(
+            ctx.put_metric("Latency", f64::NAN, Unit::Milliseconds),
+            Err(Error::NonFiniteValue(_))
+        ));
+        assert_eq!(
+            ctx.put_metric_aggregated(
+                "Size",
+                1,
+                Unit::Bytes,
+                Aggregation::Histogram {
+                    relative_accuracy: 1.5
+                }
+            ),
+            Err(Error::InvalidRelativeAccuracy(1.5))
+        );
+        assert!(ctx.metrics.is_empty());
+        assert_eq!(ctx.errors().len(), 2);
+    }
+
+    #[test]
+    fn statistic_set_metric_values_summarize() {
+        let mut values = MetricValues::new(Unit::Count, Aggregation::StatisticSet).unwrap();
+        for value in &[3.0, 1.0, 2.0] {
+            values.add(*value).unwrap();
+        }
+        match values.values {
+            Values::StatisticSet {
+                min,
+                max,
+                sum,
+                count,
+            } => {
+                assert_eq!((min, max, sum, count), (1.0, 3.0, 6.0, 3));
+            }
+            other => panic!("unexpected values {:?}", other),
+        }
+    }
+
+    #[test]
+    fn counters_and_gauges_accumulate_single_values() {
+        let mut ctx = MetricContext::default();
+        for value in &[1, 2, 3] {
+            ctx.increment("Requests", *value, Unit::Count).unwrap();
+            ctx.gauge("Connections", *value, Unit::Count).unwrap();
+        }
+        match ctx.metrics["Requests"].values {
+            Values::Sum(sum) => assert_eq!(sum, 6.0),
+            ref other => panic!("unexpected values {:?}", other),
+        }
+        match ctx.metrics["Connections"].values {
+            Values::Last(last) => assert_eq!(last, 3.0),
+            ref other => panic!("unexpected values {:?}", other),
+        }
+    }
+
+    #[test]
+    fn oversized_property_values_are_rejected() {
+        let mut ctx = MetricContext::default();
+        assert_eq!(
+            ctx.set_property("Trace", "x".repeat(validate::MAX_PROPERTY_VALUE_BYTES)),
+            Err(Error::InvalidProperty {
+                name: "Trace".into(),
+                reason: "is larger than 32 KiB"
+            })
+        );
+        assert!(ctx.properties.is_empty());
+        assert_eq!(ctx.errors().len(), 1);
+        assert_eq!(ctx.set_property("RequestId", "x".repeat(1024)), Ok(()));
+    }
+
+    #[test]
+    fn reserved_key_is_rejected() {
+        let mut ctx = MetricContext::default();
+        ctx.set_collision_policy(CollisionPolicy::Allow);
+        let _ = ctx.set_property("_aws", "oops");
+        let _ = ctx.put_metric("_aws", 1, Unit::Count);
+        assert!(ctx.properties.is_empty());
+        assert!(ctx.metrics.is_empty());
+        assert_eq!(ctx.errors().len(), 2);
+    }
+
+    #[test]
+    fn colliding_property_is_rejected() {
+        let mut ctx = MetricContext::default();
+        ctx.put_metric("Latency", 1, Unit::Milliseconds).unwrap();
+        ctx.put_dimensions(dimensions! { "Service" => "api" })
+            .unwrap();
+        let _ = ctx.set_property("Latency", "slow");
+        let _ = ctx.set_property("Service", "other");
+        assert!(ctx.properties.is_empty());
+        assert_eq!(
+            ctx.errors(),
+            &[
+                Error::KeyCollision("Latency".into()),
+                Error::KeyCollision("Service".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn conflicting_dimension_values_collide() {
+        let mut ctx = MetricContext::default();
+        ctx.put_dimensions(dimensions! { "Service" => "api" })
+            .unwrap();
+        ctx.put_dimensions(dimensions! { "Service" => "api", "Route" => "/" })
+            .unwrap();
+        assert!(ctx.errors().is_empty());
+        let _ = ctx.put_dimensions(dimensions! { "Service" => "worker" });
+        assert_eq!(ctx.errors(), &[Error::KeyCollision("Service".into())]);
+        assert_eq!(ctx.dimensions.len(), 2);
+    }
+
+    #[test]
+    fn dimension_sets_with_invalid_dimensions_are_dropped() {
+        let mut ctx = MetricContext::default();
+        let _ = ctx.put_dimensions(dimensions! { "Service" => "api", "Route" => "" });
+        assert!(ctx.dimensions.is_empty());
+        assert_eq!(
+            ctx.errors(),
+            &[Error::InvalidDimension {
+                name: "Route".into(),
+                reason: "is empty"
+            }]
+        );
+    }
+
+    #[test]
+    fn invalid_dimensions_may_be_sanitized() {
+        let mut ctx = MetricContext::default();
+        ctx.set_dimension_sanitization(Sanitization {
+            truncate: true,
+            replacement: Some('_'),
+        });
+        ctx.put_dimensions(dimensions! { "Tenant" => "Zürich".repeat(200) })
+            .unwrap();
+        assert_eq!(ctx.dimensions[0]["Tenant"].len(), MAX_DIMENSION_VALUE_LEN);
+        assert!(ctx.dimensions[0]["Tenant"].starts_with("Z_rich"));
+        assert!(ctx.errors().is_empty());
+    }
+
+    #[test]
+    fn guarded_dimensions_are_replaced() {
+        let guard = Arc::new(CardinalityGuard::new(1, std::time::Duration::from_secs(60)));
+        let mut first = MetricContext::default();
+        first.set_cardinality_guard(guard.clone());
+        first
+            .put_dimensions(dimensions! { "RequestId" => "a" })
+            .unwrap();
+        assert_eq!(first.dimensions[0]["RequestId"], "a");
+
+        let mut second = MetricContext::default();
+        second.set_cardinality_guard(guard);
+        second
+            .put_dimensions(dimensions! { "RequestId" => "b" })
+            .unwrap();
+        second
+            .put_dimensions(dimensions! { "RequestId" => "c" })
+            .unwrap();
+        assert_eq!(second.dimensions[0]["RequestId"], "Other");
+        assert!(matches!(
+            second.metrics[CARDINALITY_EXCEEDED_METRIC].values,
+            Values::Sum(count) if count == 2.0
+        ));
+    }
+
+    #[test]
+    fn guard_windows_follow_the_context_clock() {
+        let clock = ManualClock::default();
+        let guard = Arc::new(CardinalityGuard::new(1, std::time::Duration::from_secs(60)));
+        let mut first = MetricContext::default();
+        first.set_clock(clock.clone());
+        first.set_cardinality_guard(guard.clone());
+        first
+            .put_dimensions(dimensions! { "RequestId" => "a" })
+            .unwrap();
+
+        clock.advance(std::time::Duration::from_secs(60));
+        let mut second = MetricContext::default();
+        second.set_clock(clock);
+        second.set_cardinality_guard(guard);
+        second
+            .put_dimensions(dimensions! { "RequestId" => "b" })
+            .unwrap();
+        assert_eq!(second.dimensions[0]["RequestId"], "b");
+    }
+
+    #[test]
+    fn rejected_dimensions_are_not_counted_by_guards() {
+        let guard = Arc::new(CardinalityGuard::new(1, std::time::Duration::from_secs(60)));
+        let mut ctx = MetricContext::default();
+        ctx.set_cardinality_guard(guard.clone());
+        ctx.set_property("RequestId", "a").unwrap();
+        let _ = ctx.put_dimensions(dimensions! { "RequestId" => "a" });
+        assert_eq!(ctx.errors(), &[Error::KeyCollision("RequestId".into())]);
+        assert_eq!(
+            guard.admit("RequestId", "b".into(), &SystemClock),
+            ("b".into(), false)
+        );
+    }
+
+    #[test]
+    fn invalid_metric_names_are_dropped() {
+        let mut ctx = MetricContext::default();
+        let _ = ctx.put_metric("", 1, Unit::Count);
+        let _ = ctx.put_metric_with_dimensions("", 1, Unit::Count, dimensions! { "Key" => "a" });
+        assert!(ctx.metrics.is_empty());
+        assert!(ctx.directives.is_empty());
+        assert_eq!(ctx.errors().len(), 2);
+    }
+
+    #[test]
+    fn metric_names_may_be_normalized() {
+        let mut ctx = MetricContext::default();
+        ctx.set_metric_name_normalization(Normalization {
+            trim: true,
+            whitespace: Some('_'),
+            truncate: false,
+        });
+        ctx.put_metric(" Request Latency ", 1, Unit::Milliseconds)
+            .unwrap();
+        ctx.put_metric_with_dimensions("Cache Hits", 1, Unit::Count, dimensions! { "Key" => "a" })
+            .unwrap();
+        assert!(ctx.metrics.contains_key("Request_Latency"));
+        assert_eq!(ctx.directives[0].metrics, vec!["Cache_Hits".to_string()]);
+        assert!(ctx.errors().is_empty());
+    }
+
+    #[test]
+    fn metrics_with_conflicting_dimensions_are_rejected() {
+        let mut ctx = MetricContext::default();
+        ctx.put_metric_with_dimensions("A", 1, Unit::Count, dimensions! { "Op" => "get" })
+            .unwrap();
+        let _ = ctx.put_metric_with_dimensions("B", 1, Unit::Count, dimensions! { "Op" => "put" });
+        assert!(ctx.metrics.contains_key("A"));
+        assert!(!ctx.metrics.contains_key("B"));
+        assert_eq!(ctx.directives.len(), 1);
+        assert_eq!(
+            ctx.errors(),
+            &[
+                Error::KeyCollision("Op".into()),
+                Error::ConflictingDimension {
+                    metric: "B".into(),
+                    dimension: "Op".into()
+                }
+            ]
+        );
+        assert!(emf::validate(&Log.serialize(ctx)).is_ok());
+    }
+
+    #[test]
+    fn metrics_keep_their_first_dimension_set() {
+        let mut ctx = MetricContext::default();
+        ctx.put_metric_with_dimensions("Latency", 1, Unit::Count, dimensions! { "Op" => "get" })
+            .unwrap();
+        assert_eq!(
+            ctx.put_metric_with_dimensions(
+                "Latency",
+                2,
+                Unit::Count,
+                dimensions! { "Table" => "users" },
+            ),
+            Err(Error::ConflictingDimension {
+                metric: "Latency".into(),
+                dimension: "Op".into()
+            })
+        );
+        assert_eq!(ctx.directives.len(), 1);
+        assert_eq!(
+            ctx.directives[0].dimensions[0],
+            dimensions! { "Op" => "get" }
+        );
+        assert!(matches!(&ctx.metrics["Latency"].values, Values::Raw(values) if values == &[1.0]));
+        assert_eq!(
+            ctx.errors(),
+            &[Error::ConflictingDimension {
+                metric: "Latency".into(),
+                dimension: "Op".into()
+            }]
+        );
+    }
+
+    #[test]
+    fn directive_metric_names_are_normalized() {
+        let mut ctx = MetricContext::default();
+        ctx.set_metric_name_normalization(Normalization {
+            trim: true,
+            whitespace: Some('_'),
+            truncate: false,
+        });
+        ctx.put_metric("Cache Hits", 1, Unit::Count).unwrap();
+        let _ = ctx.put_directive(
+            "Other",
+            vec![],
+            vec![" Cache Hits ".to_string(), "".to_string()],
+        );
+        assert_eq!(ctx.directives[0].metrics, vec!["Cache_Hits".to_string()]);
+        assert_eq!(ctx.errors().len(), 1);
+    }
+
+    #[test]
+    fn collisions_may_be_allowed() {
+        let mut ctx = MetricContext::default();
+        ctx.set_collision_policy(CollisionPolicy::Allow);
+        ctx.set_property("Latency", "slow").unwrap();
+        let _ = ctx.put_metric("Latency", 1, Unit::Milliseconds);
+        assert!(ctx.metrics.contains_key("Latency"));
+        assert_eq!(ctx.errors(), &[Error::KeyCollision("Latency".into())]);
+    }
+
     #[test]
     fn unit_serializes() {
         for (unit, expected) in &[
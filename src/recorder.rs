@@ -0,0 +1,409 @@
+//! A recorder for the `metrics` crate facade which emits embedded metric format documents
+use crate::{
+    log::{Aggregation, Unit},
+    registry::{Key, MetricsRegistry},
+    sketch::DEFAULT_RELATIVE_ACCURACY,
+};
+use metrics::{
+    Counter, CounterFn, Gauge, GaugeFn, Histogram, HistogramFn, KeyName, Metadata, Recorder,
+    SharedString,
+};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// The registry a recorder aggregates into
+#[derive(Clone)]
+enum Registry {
+    Global,
+    Owned(Arc<MetricsRegistry>),
+}
+
+impl Registry {
+    fn get(&self) -> &MetricsRegistry {
+        match self {
+            Registry::Global => MetricsRegistry::global(),
+            Registry::Owned(registry) => registry,
+        }
+    }
+}
+
+/// Maps units of the `metrics` crate onto CloudWatch units.
+/// CloudWatch has no binary byte units, so kibibytes, mebibytes, gibibytes and
+/// tebibytes map to kilobytes, megabytes, gigabytes and terabytes without scaling
+/// their values. Units CloudWatch has no equivalent for map to `Unit::None`
+fn unit(unit: metrics::Unit) -> Unit {
+    match unit {
+        metrics::Unit::Count => Unit::Count,
+        metrics::Unit::Percent => Unit::Percent,
+        metrics::Unit::Seconds => Unit::Seconds,
+        metrics::Unit::Milliseconds => Unit::Milliseconds,
+        metrics::Unit::Microseconds => Unit::Microseconds,
+        metrics::Unit::Tebibytes => Unit::Terabytes,
+        metrics::Unit::Gibibytes => Unit::Gigabytes,
+        metrics::Unit::Mebibytes => Unit::Megabytes,
+        metrics::Unit::Kibibytes => Unit::Kilobytes,
+        metrics::Unit::Bytes => Unit::Bytes,
+        metrics::Unit::TerabitsPerSecond => Unit::TerabitsPerSecond,
+        metrics::Unit::GigabitsPerSecond => Unit::GigabitsPerSecond,
+        metrics::Unit::MegabitsPerSecond => Unit::MegabitsPerSecond,
+        metrics::Unit::KilobitsPerSecond => Unit::KilobitsPerSecond,
+        metrics::Unit::BitsPerSecond => Unit::BitsPerSecond,
+        metrics::Unit::CountPerSecond => Unit::CountPerSecond,
+        _ => Unit::None,
+    }
+}
+
+/// A `metrics::Recorder` which aggregates counters, gauges and histograms in a
+/// `MetricsRegistry`
+///
+/// Metrics are published under the recorder's namespace with their labels as
+/// dimensions. Units given when describing a metric are used for values recorded
+/// after it is described, otherwise counters are emitted as `Count` and everything
+/// else without a unit. Binary byte units are emitted as their decimal counterparts
+///
+/// # example
+/// ```rust,edition2018
+/// use aws_embedded_metrics::EmfRecorder;
+///
+/// # fn main() {
+/// metrics::set_global_recorder(EmfRecorder::new("MyApp")).unwrap();
+/// metrics::counter!("Requests", "Route" => "/users").increment(1);
+/// # }
+/// ```
+pub struct EmfRecorder {
+    namespace: String,
+    registry: Registry,
+    units: Arc<Mutex<HashMap<String, Unit>>>,
+    /// Handles keep the state of gauges and absolute counters, so each key shares one
+    handles: Mutex<HashMap<(Kind, metrics::Key), Arc<Handle>>>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Kind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+impl EmfRecorder {
+    /// Creates a recorder which aggregates into the global registry,
+    /// flushed on its interval by a background thread
+    pub fn new(namespace: impl Into<String>) -> Self {
+        EmfRecorder {
+            namespace: namespace.into(),
+            registry: Registry::Global,
+            units: Arc::default(),
+            handles: Mutex::default(),
+        }
+    }
+
+    /// Creates a recorder which aggregates into the given registry,
+    /// flushed on its interval by a thread started with `MetricsRegistry::spawn_flusher`
+    pub fn with_registry(
+        namespace: impl Into<String>,
+        registry: Arc<MetricsRegistry>,
+    ) -> Self {
+        registry.spawn_flusher();
+        EmfRecorder {
+            registry: Registry::Owned(registry),
+            ..EmfRecorder::new(namespace)
+        }
+    }
+
+    fn describe(
+        &self,
+        name: KeyName,
+        described: Option<metrics::Unit>,
+    ) {
+        if let Some(described) = described {
+            self.units
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .insert(name.as_str().into(), unit(described));
+        }
+    }
+
+    fn handle(
+        &self,
+        kind: Kind,
+        key: &metrics::Key,
+    ) -> Arc<Handle> {
+        self.handles
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .entry((kind, key.clone()))
+            .or_insert_with(|| Arc::new(self.new_handle(kind, key)))
+            .clone()
+    }
+
+    fn new_handle(
+        &self,
+        kind: Kind,
+        key: &metrics::Key,
+    ) -> Handle {
+        let default_unit = match kind {
+            Kind::Counter => Unit::Count,
+            Kind::Gauge | Kind::Histogram => Unit::None,
+        };
+        Handle {
+            registry: self.registry.clone(),
+            key: (
+                self.namespace.clone(),
+                key.labels()
+                    .map(|label| (label.key().into(), label.value().into()))
+                    .collect(),
+            ),
+            name: key.name().into(),
+            units: self.units.clone(),
+            default_unit,
+            value: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Recorder for EmfRecorder {
+    fn describe_counter(
+        &self,
+        key: KeyName,
+        unit: Option<metrics::Unit>,
+        _: SharedString,
+    ) {
+        self.describe(key, unit)
+    }
+
+    fn describe_gauge(
+        &self,
+        key: KeyName,
+        unit: Option<metrics::Unit>,
+        _: SharedString,
+    ) {
+        self.describe(key, unit)
+    }
+
+    fn describe_histogram(
+        &self,
+        key: KeyName,
+        unit: Option<metrics::Unit>,
+        _: SharedString,
+    ) {
+        self.describe(key, unit)
+    }
+
+    fn register_counter(
+        &self,
+        key: &metrics::Key,
+        _: &Metadata<'_>,
+    ) -> Counter {
+        Counter::from_arc(self.handle(Kind::Counter, key))
+    }
+
+    fn register_gauge(
+        &self,
+        key: &metrics::Key,
+        _: &Metadata<'_>,
+    ) -> Gauge {
+        Gauge::from_arc(self.handle(Kind::Gauge, key))
+    }
+
+    fn register_histogram(
+        &self,
+        key: &metrics::Key,
+        _: &Metadata<'_>,
+    ) -> Histogram {
+        Histogram::from_arc(self.handle(Kind::Histogram, key))
+    }
+}
+
+/// Records one metric into the registry
+struct Handle {
+    registry: Registry,
+    key: Key,
+    name: String,
+    /// Units of described metrics, looked up when each value is recorded
+    units: Arc<Mutex<HashMap<String, Unit>>>,
+    default_unit: Unit,
+    /// The last absolute counter value, or the bits of the current gauge value
+    value: AtomicU64,
+}
+
+impl Handle {
+    fn unit(&self) -> Unit {
+        self.units
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(&self.name)
+            .copied()
+            .unwrap_or(self.default_unit)
+    }
+
+    fn record(
+        &self,
+        value: f64,
+        aggregation: Aggregation,
+    ) {
+        self.registry.get().record(
+            &self.key,
+            self.name.clone(),
+            value,
+            self.unit(),
+            aggregation,
+        )
+    }
+
+    /// Updates the gauge while the registry is locked, so concurrent updates
+    /// are recorded in the order they were applied
+    fn update_gauge(
+        &self,
+        update: impl Fn(f64) -> f64,
+    ) {
+        self.registry.get().record_with(
+            &self.key,
+            self.name.clone(),
+            self.unit(),
+            Aggregation::Last,
+            || {
+                let value = update(f64::from_bits(self.value.load(Ordering::Acquire)));
+                self.value.store(value.to_bits(), Ordering::Release);
+                value
+            },
+        );
+    }
+}
+
+impl CounterFn for Handle {
+    fn increment(
+        &self,
+        value: u64,
+    ) {
+        self.record(value as f64, Aggregation::Sum)
+    }
+
+    /// Absolute values are emitted as the increase since the last absolute value
+    fn absolute(
+        &self,
+        value: u64,
+    ) {
+        let last = self.value.fetch_max(value, Ordering::AcqRel);
+        self.record(value.saturating_sub(last) as f64, Aggregation::Sum)
+    }
+}
+
+impl GaugeFn for Handle {
+    fn increment(
+        &self,
+        value: f64,
+    ) {
+        self.update_gauge(|current| current + value)
+    }
+
+    fn decrement(
+        &self,
+        value: f64,
+    ) {
+        self.update_gauge(|current| current - value)
+    }
+
+    fn set(
+        &self,
+        value: f64,
+    ) {
+        self.update_gauge(|_| value)
+    }
+}
+
+impl HistogramFn for Handle {
+    fn record(
+        &self,
+        value: f64,
+    ) {
+        Handle::record(
+            self,
+            value,
+            Aggregation::Histogram {
+                relative_accuracy: DEFAULT_RELATIVE_ACCURACY,
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assert_dimension, assert_metric, log::Values, testing::MemorySink};
+
+    #[test]
+    fn recorder_maps_metrics_onto_registry() {
+        let sink = MemorySink::default();
+        let registry = Arc::new(MetricsRegistry::with_sink(sink.clone()));
+        let recorder = EmfRecorder::with_registry("MyApp", registry.clone());
+        metrics::with_local_recorder(&recorder, || {
+            metrics::describe_histogram!("Latency", metrics::Unit::Milliseconds, "");
+            metrics::counter!("Requests", "Route" => "/users").increment(2);
+            metrics::counter!("Requests", "Route" => "/users").increment(3);
+            metrics::counter!("Total", "Route" => "/users").absolute(10);
+            metrics::counter!("Total", "Route" => "/users").absolute(15);
+            let connections = metrics::gauge!("Connections", "Route" => "/users");
+            connections.set(4.0);
+            connections.increment(2.0);
+            connections.decrement(1.0);
+            metrics::histogram!("Latency", "Route" => "/users").record(12.0);
+        });
+        assert_eq!(registry.flush(), Ok(()));
+
+        let contexts = sink.contexts();
+        assert_eq!(contexts.len(), 1);
+        assert_eq!(contexts[0].namespace, "MyApp");
+        assert_dimension!(sink, "Route", "/users");
+        assert_metric!(sink, "Requests", Unit::Count);
+        assert_eq!(sink.values("Requests"), vec![5.0]);
+        assert_eq!(sink.values("Total"), vec![15.0]);
+        assert_eq!(sink.values("Connections"), vec![5.0]);
+        assert!(matches!(
+            contexts[0].metrics["Connections"].values,
+            Values::Last(_)
+        ));
+        assert_metric!(sink, "Latency", Unit::Milliseconds);
+        assert!(matches!(
+            contexts[0].metrics["Latency"].values,
+            Values::Histogram(_)
+        ));
+    }
+
+    #[test]
+    fn recorder_uses_units_described_after_registration() {
+        let sink = MemorySink::default();
+        let registry = Arc::new(MetricsRegistry::with_sink(sink.clone()));
+        let recorder = EmfRecorder::with_registry("MyApp", registry.clone());
+        metrics::with_local_recorder(&recorder, || {
+            let latency = metrics::histogram!("Latency");
+            metrics::describe_histogram!("Latency", metrics::Unit::Milliseconds, "");
+            latency.record(12.0);
+        });
+        assert_eq!(registry.flush(), Ok(()));
+        assert_metric!(sink, "Latency", Unit::Milliseconds);
+    }
+
+    #[test]
+    fn recorder_records_concurrent_gauge_updates_in_order() {
+        let sink = MemorySink::default();
+        let registry = Arc::new(MetricsRegistry::with_sink(sink.clone()));
+        let recorder = EmfRecorder::with_registry("MyApp", registry.clone());
+        let gauge = metrics::with_local_recorder(&recorder, || metrics::gauge!("Connections"));
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    for _ in 0..1000 {
+                        gauge.increment(1.0);
+                    }
+                });
+            }
+        });
+        assert_eq!(registry.flush(), Ok(()));
+        assert_eq!(sink.values("Connections"), vec![8000.0]);
+    }
+}
@@ -0,0 +1,452 @@
+//! A process-wide registry of metrics aggregated in memory and flushed on an interval
+//!
+//! Where `metric_scope` emits one log event per unit of work, the registry
+//! accumulates counters, gauges and histograms across units of work and emits
+//! one log event per namespace and dimension set each time it is flushed
+use crate::{
+    clock::{Clock, SystemClock},
+    env::{Detector, EnvironmentProvider},
+    error::{self, Error},
+    log::{Aggregation, MetricContext, MetricValues, Unit},
+    sink::Sink,
+    sketch::DEFAULT_RELATIVE_ACCURACY,
+    validate::{self, Normalization},
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    mem,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard, OnceLock, Weak,
+    },
+    thread,
+    time::Duration,
+};
+
+/// How often the global registry is flushed unless configured otherwise
+const DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+static GLOBAL: OnceLock<MetricsRegistry> = OnceLock::new();
+
+pub(crate) type Key = (String, BTreeMap<String, String>);
+
+/// Aggregates metrics in memory, keyed by namespace, dimension set and metric name
+///
+/// # example
+/// ```rust,edition2018
+/// use aws_embedded_metrics::{dimensions, MetricsRegistry, Unit};
+///
+/// # fn main() {
+/// let requests = MetricsRegistry::global().scope("MyApp", dimensions! {
+///     "Route" => "/users"
+/// });
+/// requests.increment("Requests", 1, Unit::Count);
+/// requests.histogram("Latency", 12, Unit::Milliseconds);
+/// # }
+/// ```
+pub struct MetricsRegistry {
+    aggregates: Mutex<HashMap<Key, HashMap<String, MetricValues>>>,
+    /// Errors raised while recording, returned by the next flush
+    errors: Mutex<Vec<Error>>,
+    sink: Mutex<Option<Arc<Mutex<dyn Sink>>>>,
+    clock: Mutex<Arc<dyn Clock>>,
+    flush_interval_millis: AtomicU64,
+    last_flushed_millis: AtomicU64,
+}
+
+impl Default for MetricsRegistry {
+    fn default() -> MetricsRegistry {
+        MetricsRegistry {
+            aggregates: Mutex::default(),
+            errors: Mutex::default(),
+            sink: Mutex::default(),
+            clock: Mutex::new(Arc::new(SystemClock)),
+            flush_interval_millis: AtomicU64::new(DEFAULT_FLUSH_INTERVAL.as_millis() as u64),
+            last_flushed_millis: AtomicU64::new(SystemClock.now_millis()),
+        }
+    }
+}
+
+/// Recovers the guarded value of a mutex poisoned by a panicking recorder
+fn lock<T: ?Sized>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl MetricsRegistry {
+    /// Creates a registry which flushes to the given sink rather than one
+    /// resolved from the environment. Such registries are only flushed when you call `flush`,
+    /// unless you start a flusher with `spawn_flusher`
+    pub fn with_sink(sink: impl Sink + 'static) -> MetricsRegistry {
+        MetricsRegistry {
+            sink: Mutex::new(Some(Arc::new(Mutex::new(sink)))),
+            ..MetricsRegistry::default()
+        }
+    }
+
+    /// Returns the process-wide registry.
+    ///
+    /// The first call starts a background thread which flushes the registry
+    /// every 60 seconds, or at the interval set with `set_flush_interval`.
+    /// Metrics recorded since the last interval are only emitted on exit if
+    /// you call `flush` yourself
+    pub fn global() -> &'static MetricsRegistry {
+        GLOBAL.get_or_init(|| {
+            thread::spawn(|| loop {
+                let registry = MetricsRegistry::global();
+                thread::sleep(registry.until_due());
+                registry.flush_if_due();
+            });
+            MetricsRegistry::default()
+        })
+    }
+
+    /// Starts a background thread which flushes the registry on its flush interval,
+    /// as the global registry is. The thread stops once the registry is dropped
+    ///
+    /// # example
+    /// ```rust,edition2018
+    /// use aws_embedded_metrics::MetricsRegistry;
+    /// use std::{sync::Arc, time::Duration};
+    ///
+    /// # fn main() {
+    /// let registry = Arc::new(MetricsRegistry::default());
+    /// registry.set_flush_interval(Duration::from_secs(10));
+    /// registry.spawn_flusher();
+    /// # }
+    /// ```
+    pub fn spawn_flusher(self: &Arc<Self>) {
+        let registry = Arc::downgrade(self);
+        thread::spawn(move || {
+            let until_due = |registry: &Weak<MetricsRegistry>| {
+                registry.upgrade().map(|registry| registry.until_due())
+            };
+            while let Some(wait) = until_due(&registry) {
+                thread::sleep(wait);
+                match registry.upgrade() {
+                    Some(registry) => registry.flush_if_due(),
+                    None => break,
+                };
+            }
+        });
+    }
+
+    /// Sets the sink flushed metrics are written to, in place of one resolved from
+    /// the environment. Use this to direct the global registry to a sink of your own
+    pub fn set_sink(
+        &self,
+        sink: impl Sink + 'static,
+    ) {
+        *lock(&self.sink) = Some(Arc::new(Mutex::new(sink)));
+    }
+
+    /// Returns the interval on which the registry is flushed
+    pub fn flush_interval(&self) -> Duration {
+        Duration::from_millis(self.flush_interval_millis.load(Ordering::Relaxed))
+    }
+
+    /// Sets the interval on which the registry is flushed by its flusher thread.
+    /// This takes effect after the next scheduled flush
+    ///
+    /// # Panics
+    ///
+    /// Panics if the interval is shorter than a millisecond, which would have the
+    /// flusher thread flush continuously
+    pub fn set_flush_interval(
+        &self,
+        interval: Duration,
+    ) {
+        let millis = interval.as_millis() as u64;
+        assert!(millis > 0, "flush interval must be at least a millisecond");
+        self.flush_interval_millis.store(millis, Ordering::Relaxed);
+    }
+
+    /// Sets the clock which timestamps flushed metrics and decides when a flush is due.
+    /// The flush interval restarts from the clock's current time
+    pub fn set_clock(
+        &self,
+        clock: impl Clock + 'static,
+    ) {
+        let clock: Arc<dyn Clock> = Arc::new(clock);
+        self.last_flushed_millis
+            .store(clock.now_millis(), Ordering::Relaxed);
+        *lock(&self.clock) = clock;
+    }
+
+    /// Time left on the registry's clock until the flush interval elapses
+    fn until_due(&self) -> Duration {
+        let now = lock(&self.clock).now_millis();
+        let elapsed = now.saturating_sub(self.last_flushed_millis.load(Ordering::Relaxed));
+        self.flush_interval()
+            .saturating_sub(Duration::from_millis(elapsed))
+    }
+
+    /// Flushes the registry if the flush interval has elapsed on its clock since
+    /// the last flush, returning whether it did. Errors are only written to stderr,
+    /// when debug logging is enabled
+    pub fn flush_if_due(&self) -> bool {
+        if !self.until_due().is_zero() {
+            return false;
+        }
+        let _ = self.flush();
+        true
+    }
+
+    /// Returns a handle for recording metrics under a namespace and dimension set
+    pub fn scope(
+        &self,
+        namespace: impl Into<String>,
+        dimensions: HashMap<String, String>,
+    ) -> RegistryScope<'_> {
+        RegistryScope {
+            registry: self,
+            key: (namespace.into(), dimensions.into_iter().collect()),
+        }
+    }
+
+    pub(crate) fn record(
+        &self,
+        key: &Key,
+        name: String,
+        value: f64,
+        unit: Unit,
+        aggregation: Aggregation,
+    ) {
+        self.record_with(key, name, unit, aggregation, || value)
+    }
+
+    /// Records the value returned by `value`, which is called while the registry is locked
+    /// so that values computed from shared state are recorded in the order they were computed
+    pub(crate) fn record_with(
+        &self,
+        key: &Key,
+        name: String,
+        unit: Unit,
+        aggregation: Aggregation,
+        value: impl FnOnce() -> f64,
+    ) {
+        let name = match validate::metric_name(&name, Normalization::default()) {
+            Ok(_) => name,
+            Err(reason) => {
+                lock(&self.errors).push(Error::InvalidMetricName { name, reason });
+                return;
+            }
+        };
+        let mut aggregates = lock(&self.aggregates);
+        let value = value();
+        let added = match aggregates
+            .get_mut(key)
+            .and_then(|metrics| metrics.get_mut(&name))
+        {
+            Some(metric) => metric.add(value),
+            None => MetricValues::with_value(unit, aggregation, value).map(|metric| {
+                aggregates
+                    .entry(key.clone())
+                    .or_default()
+                    .insert(name, metric);
+            }),
+        };
+        drop(aggregates);
+        if let Err(err) = added {
+            lock(&self.errors).push(err);
+        }
+    }
+
+    /// Emits one `MetricContext` per namespace and dimension set to the configured sink
+    /// and resets all aggregates.
+    ///
+    /// Returns any errors raised while recording, serializing or sending the metrics,
+    /// which are also written to stderr when `AWS_EMF_ENABLE_DEBUG_LOGGING` is set
+    pub fn flush(&self) -> Result<(), Vec<Error>> {
+        let clock = lock(&self.clock).clone();
+        self.last_flushed_millis
+            .store(clock.now_millis(), Ordering::Relaxed);
+        let aggregates = mem::take(&mut *lock(&self.aggregates));
+        let mut errors = mem::take(&mut *lock(&self.errors));
+        if aggregates.is_empty() {
+            return error::report(errors);
+        }
+        let sink = lock(&self.sink)
+            .get_or_insert_with(|| Detector.get().sink)
+            .clone();
+        let mut sink = lock(&sink);
+        for ((namespace, dimensions), entries) in aggregates {
+            let mut context = MetricContext::default();
+            context.set_shared_clock(clock.clone());
+            context.set_namespace(namespace);
+            if !dimensions.is_empty() {
+                let _ = context.put_dimensions(dimensions.into_iter().collect());
+            }
+            context.metrics = entries;
+            errors.append(&mut context.errors);
+            errors.extend(sink.accept(context).err().into_iter().flatten());
+        }
+        error::report(errors)
+    }
+}
+
+/// Records metrics into a `MetricsRegistry` under a fixed namespace and dimension set
+pub struct RegistryScope<'a> {
+    registry: &'a MetricsRegistry,
+    key: Key,
+}
+
+impl<'a> RegistryScope<'a> {
+    /// Adds to a counter, emitted as the sum of all increments since the last flush
+    pub fn increment(
+        &self,
+        name: impl Into<String>,
+        by: impl Into<f64>,
+        unit: Unit,
+    ) {
+        self.registry
+            .record(&self.key, name.into(), by.into(), unit, Aggregation::Sum);
+    }
+
+    /// Sets a gauge, emitted as the last value set since the last flush
+    pub fn gauge(
+        &self,
+        name: impl Into<String>,
+        value: impl Into<f64>,
+        unit: Unit,
+    ) {
+        self.registry.record(
+            &self.key,
+            name.into(),
+            value.into(),
+            unit,
+            Aggregation::Last,
+        );
+    }
+
+    /// Records a value in a histogram, emitted as a distribution of the values
+    /// recorded since the last flush
+    pub fn histogram(
+        &self,
+        name: impl Into<String>,
+        value: impl Into<f64>,
+        unit: Unit,
+    ) {
+        self.registry.record(
+            &self.key,
+            name.into(),
+            value.into(),
+            unit,
+            Aggregation::Histogram {
+                relative_accuracy: DEFAULT_RELATIVE_ACCURACY,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::ManualClock, dimensions, log::Values, testing::MemorySink};
+
+    #[test]
+    fn registry_aggregates_per_dimension_set() {
+        let sink = MemorySink::default();
+        let registry = MetricsRegistry::with_sink(sink.clone());
+        let a = registry.scope("test", dimensions! { "Route" => "a" });
+        let b = registry.scope("test", dimensions! { "Route" => "b" });
+        a.increment("Requests", 1, Unit::Count);
+        a.increment("Requests", 2, Unit::Count);
+        a.gauge("Connections", 3, Unit::Count);
+        a.gauge("Connections", 5, Unit::Count);
+        b.histogram("Latency", 10, Unit::Milliseconds);
+        assert_eq!(registry.flush(), Ok(()));
+
+        let contexts = sink.contexts();
+        assert_eq!(contexts.len(), 2);
+        let a = contexts
+            .iter()
+            .find(|ctx| ctx.dimensions[0]["Route"] == "a")
+            .unwrap();
+        match &a.metrics["Requests"].values {
+            Values::Sum(sum) => assert_eq!(*sum, 3.0),
+            other => panic!("unexpected values {:?}", other),
+        }
+        match &a.metrics["Connections"].values {
+            Values::Last(last) => assert_eq!(*last, 5.0),
+            other => panic!("unexpected values {:?}", other),
+        }
+    }
+
+    #[test]
+    fn registry_resets_after_flush() {
+        let sink = MemorySink::default();
+        let registry = MetricsRegistry::with_sink(sink.clone());
+        registry
+            .scope("test", HashMap::new())
+            .increment("Requests", 1, Unit::Count);
+        assert_eq!(registry.flush(), Ok(()));
+        assert_eq!(registry.flush(), Ok(()));
+        assert_eq!(sink.contexts().len(), 1);
+    }
+
+    #[test]
+    fn registry_flushes_when_due_on_its_clock() {
+        let sink = MemorySink::default();
+        let clock = ManualClock::new(Duration::from_secs(1));
+        let registry = MetricsRegistry::with_sink(sink.clone());
+        registry.set_clock(clock.clone());
+        registry.set_flush_interval(Duration::from_secs(10));
+        registry
+            .scope("test", HashMap::new())
+            .increment("Requests", 1, Unit::Count);
+        clock.advance(Duration::from_secs(9));
+        assert!(!registry.flush_if_due());
+        clock.advance(Duration::from_secs(1));
+        assert!(registry.flush_if_due());
+
+        let contexts = sink.contexts();
+        assert_eq!(contexts.len(), 1);
+        assert_eq!(contexts[0].meta["Timestamp"], 11_000);
+    }
+
+    #[test]
+    fn registry_reports_invalid_names_and_dimensions() {
+        let sink = MemorySink::default();
+        let registry = MetricsRegistry::with_sink(sink.clone());
+        registry
+            .scope("test", HashMap::new())
+            .increment("", 1, Unit::Count);
+        registry
+            .scope("test", dimensions! { "Route" => "" })
+            .increment("Requests", 1, Unit::Count);
+        let errors = registry.flush().unwrap_err();
+        assert!(matches!(&errors[0], Error::InvalidMetricName { name, .. } if name.is_empty()));
+        assert!(matches!(&errors[1], Error::InvalidDimension { name, .. } if name == "Route"));
+        assert_eq!(sink.values("Requests"), vec![1.0]);
+        assert_eq!(registry.flush(), Ok(()));
+    }
+
+    #[test]
+    #[should_panic(expected = "flush interval must be at least a millisecond")]
+    fn registry_rejects_zero_flush_interval() {
+        MetricsRegistry::default().set_flush_interval(Duration::ZERO);
+    }
+
+    #[test]
+    fn registry_flusher_flushes_when_due() {
+        let sink = MemorySink::default();
+        let clock = ManualClock::new(Duration::from_secs(1));
+        let registry = Arc::new(MetricsRegistry::with_sink(sink.clone()));
+        registry.set_clock(clock.clone());
+        registry.set_flush_interval(Duration::from_millis(10));
+        registry
+            .scope("test", HashMap::new())
+            .increment("Requests", 1, Unit::Count);
+        registry.spawn_flusher();
+        clock.advance(Duration::from_millis(10));
+        for _ in 0..100 {
+            if !sink.contexts().is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(sink.values("Requests"), vec![1.0]);
+    }
+}
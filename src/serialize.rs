@@ -1,12 +1,29 @@
-use crate::log::{MetricContext, MetricValues, Unit};
+use crate::{
+    clock::{Clock, FixedClock},
+    error::Error,
+    log::{MetricContext, MetricValues, Unit, Values},
+};
 use serde::Serialize as SerdeSerialize;
-use serde_json::Value;
-use std::collections::BTreeMap;
+use serde_json::{json, Value};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    mem,
+};
 
 // https://docs.aws.amazon.com/AmazonCloudWatch/latest/monitoring/CloudWatch_Embedded_Metric_Format_Specification.html?shortFooter=true
 
 /// Each dimension set is capped at maximum of 9 dimension names
-const MAX_DIMENSIONS: usize = 9;
+pub(crate) const MAX_DIMENSIONS: usize = 9;
+
+/// Each metric may hold at most 100 values
+pub(crate) const MAX_VALUES: usize = 100;
+
+/// CloudWatch Logs rejects events larger than 1 MiB
+pub const MAX_EVENT_BYTES: usize = 1024 * 1024;
+
+/// A UDP datagram carries at most 65,507 bytes of payload over IPv4, well under
+/// the 256 KB the CloudWatch agent would otherwise accept
+pub const MAX_DATAGRAM_BYTES: usize = 65_507;
 
 #[derive(SerdeSerialize)]
 #[serde(rename_all = "PascalCase")]
@@ -26,7 +43,7 @@ struct MetricDefinition<'a> {
 #[derive(SerdeSerialize)]
 #[serde(rename_all = "PascalCase")]
 struct Metadata<'a> {
-    cloud_watch_metrics: [MetricDefinition<'a>; 1],
+    cloud_watch_metrics: Vec<MetricDefinition<'a>>,
     #[serde(flatten)]
     meta: BTreeMap<&'a str, Value>,
 }
@@ -38,11 +55,237 @@ struct Payload<'a> {
     target_values: BTreeMap<&'a str, Value>,
 }
 
+/// Renders recorded values in the most compact form EMF accepts for them
+fn target_value(values: &Values) -> Value {
+    match values {
+        // if there is only one metric value, unwrap it to make querying easier
+        Values::Raw(values) if values.len() == 1 => values[0].into(),
+        Values::Raw(values) => values.to_owned().into(),
+        Values::Sum(value) | Values::Last(value) => (*value).into(),
+        Values::Compressed { values, counts } => json!({
+            "Values": values,
+            "Counts": counts,
+        }),
+        Values::StatisticSet {
+            min,
+            max,
+            sum,
+            count,
+        } => json!({
+            "Max": max,
+            "Min": min,
+            "SampleCount": count,
+            "Sum": sum,
+        }),
+        Values::Histogram(sketch) => {
+            let (values, counts) = sketch.values_counts();
+            json!({
+                "Values": values,
+                "Counts": counts,
+            })
+        }
+    }
+}
+
 pub trait Serialize {
     fn serialize(
         &self,
         context: MetricContext,
     ) -> String;
+
+    /// Serializes a context into as many documents as needed for each to fit within `max_bytes`
+    /// and hold at most 100 values per metric.
+    ///
+    /// Every document repeats the context's metadata, dimensions and properties while
+    /// metric values are spread across them. Properties and metric values which cannot
+    /// fit in any document are dropped and reported as errors
+    fn serialize_within(
+        &self,
+        context: MetricContext,
+        max_bytes: usize,
+    ) -> (Vec<String>, Vec<Error>) {
+        let whole = self.serialize(context.clone());
+        let countable = context
+            .metrics
+            .values()
+            .all(|metric| value_count(&metric.values) <= MAX_VALUES);
+        if whole.len() <= max_bytes && countable {
+            return (vec![whole], Vec::new());
+        }
+        split(self, context, max_bytes)
+    }
+}
+
+/// Number of values a metric is rendered with
+fn value_count(values: &Values) -> usize {
+    match values {
+        Values::Raw(values) | Values::Compressed { values, .. } => values.len(),
+        Values::Histogram(sketch) => sketch.values_counts().0.len(),
+        _ => 1,
+    }
+}
+
+/// Splits off the values after the first `at`, if there are any. Histograms are
+/// split as the compressed values and counts they are rendered with
+fn split_off(
+    values: &mut Values,
+    at: usize,
+) -> Option<Values> {
+    if value_count(values) <= at {
+        return None;
+    }
+    if let Values::Histogram(sketch) = values {
+        let (buckets, counts) = sketch.values_counts();
+        *values = Values::Compressed {
+            values: buckets,
+            counts,
+        };
+    }
+    match values {
+        Values::Raw(values) => Some(Values::Raw(values.split_off(at))),
+        Values::Compressed { values, counts } => Some(Values::Compressed {
+            values: values.split_off(at),
+            counts: counts.split_off(at),
+        }),
+        _ => None,
+    }
+}
+
+/// Bytes a metric adds to a serialized context, including separators
+fn measure<S: Serialize + ?Sized>(
+    serializer: &S,
+    minimal: &MetricContext,
+    minimal_size: usize,
+    name: &str,
+    values: &MetricValues,
+) -> usize {
+    let mut context = minimal.clone();
+    context.metrics.insert(name.into(), values.clone());
+    serializer
+        .serialize(context)
+        .len()
+        .saturating_sub(minimal_size)
+        + 2
+}
+
+/// Divides metric values into pieces which each hold at most `MAX_VALUES` values
+/// and fit within `room` bytes
+fn pieces<S: Serialize + ?Sized>(
+    serializer: &S,
+    minimal: &MetricContext,
+    minimal_size: usize,
+    name: &str,
+    mut metric: MetricValues,
+    room: usize,
+) -> Option<Vec<(MetricValues, usize)>> {
+    let count = value_count(&metric.values);
+    let at = if count > MAX_VALUES {
+        MAX_VALUES
+    } else {
+        let size = measure(serializer, minimal, minimal_size, name, &metric);
+        if size <= room {
+            return Some(vec![(metric, size)]);
+        }
+        count / 2
+    };
+    let rest = split_off(&mut metric.values, at.max(1))?;
+    let rest = MetricValues {
+        values: rest,
+        ..metric.clone()
+    };
+    let mut head = pieces(serializer, minimal, minimal_size, name, metric, room)?;
+    head.extend(pieces(serializer, minimal, minimal_size, name, rest, room)?);
+    Some(head)
+}
+
+fn split<S: Serialize + ?Sized>(
+    serializer: &S,
+    mut base: MetricContext,
+    max_bytes: usize,
+) -> (Vec<String>, Vec<Error>) {
+    let mut errors = Vec::new();
+    let metrics = mem::take(&mut base.metrics);
+
+    // properties don't change the size of metrics so they are measured without them
+    let mut minimal = base.clone();
+    minimal.properties.clear();
+    let minimal_size = serializer.serialize(minimal.clone()).len();
+
+    // leave room for at least a single value of the largest metric
+    let required = metrics
+        .iter()
+        .map(|(name, metric)| {
+            let mut smallest = metric.clone();
+            split_off(&mut smallest.values, 1);
+            measure(serializer, &minimal, minimal_size, name, &smallest)
+        })
+        .max()
+        .unwrap_or_default();
+    let mut base_size = serializer.serialize(base.clone()).len();
+    while base_size + required > max_bytes {
+        let largest = base
+            .properties
+            .iter()
+            .max_by_key(|(name, value)| name.len() + value.to_string().len())
+            .map(|(name, _)| name.clone());
+        match largest {
+            Some(name) => {
+                base.properties.remove(&name);
+                errors.push(Error::PropertyTooLarge(name));
+                base_size = serializer.serialize(base.clone()).len();
+            }
+            None => break,
+        }
+    }
+    if base_size > max_bytes {
+        errors.push(Error::EventTooLarge {
+            size: base_size,
+            max_bytes,
+        });
+        return (Vec::new(), errors);
+    }
+
+    // packing metrics in name order keeps the split stable between runs
+    let mut metrics: Vec<_> = metrics.into_iter().collect();
+    metrics.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+
+    let room = max_bytes - base_size;
+    let mut documents: Vec<(MetricContext, usize)> = Vec::new();
+    for (name, metric) in metrics {
+        let pieces = match pieces(serializer, &minimal, minimal_size, &name, metric, room) {
+            Some(pieces) => pieces,
+            None => {
+                errors.push(Error::MetricTooLarge(name));
+                continue;
+            }
+        };
+        for (piece, size) in pieces {
+            let document = documents.iter_mut().find(|(document, used)| {
+                !document.metrics.contains_key(&name) && used + size <= max_bytes
+            });
+            match document {
+                Some((document, used)) => {
+                    document.metrics.insert(name.clone(), piece);
+                    *used += size;
+                }
+                None => {
+                    let mut document = base.clone();
+                    document.metrics.insert(name.clone(), piece);
+                    documents.push((document, base_size + size));
+                }
+            }
+        }
+    }
+    if documents.is_empty() {
+        documents.push((base, base_size));
+    }
+    (
+        documents
+            .into_iter()
+            .map(|(document, _)| serializer.serialize(document))
+            .collect(),
+        errors,
+    )
 }
 
 pub struct Log;
@@ -52,90 +295,403 @@ impl Serialize for Log {
         &self,
         context: MetricContext,
     ) -> String {
-        let MetricContext {
-            namespace,
-            meta,
-            properties,
-            dimensions,
-            metrics,
-        } = context;
-
-        let (dimensions, mut target_values) = dimensions.iter().fold(
-            (Vec::new(), BTreeMap::new()),
-            |(mut keys, mut dims), dim| {
-                keys.push(
-                    dim.keys()
-                        .take(MAX_DIMENSIONS)
-                        .map(|s| s.as_str())
-                        .collect(),
-                );
-                dims.append(
-                    &mut dim
-                        .iter()
-                        .map(|(key, value)| (key.as_str(), Value::from(value.clone())))
-                        .collect(),
-                );
-                (keys, dims)
-            },
+        render(context, false)
+    }
+}
+
+/// Serializes like `Log`, but lists metrics and dimension names in sorted order
+/// and stamps every document with a timestamp from the given clock, so the same
+/// recorded metrics always produce the same payload.
+///
+/// Useful for comparing payloads against golden files in tests
+///
+/// # example
+/// ```rust,edition2018
+/// use aws_embedded_metrics::{Deterministic, MetricContext, Serialize, Unit};
+///
+/// # fn main() {
+/// let mut context = MetricContext::default();
+/// context.put_metric("Latency", 100, Unit::Milliseconds);
+/// assert_eq!(
+///     Deterministic::at(0).serialize(context),
+///     r#"{"_aws":{"CloudWatchMetrics":[{"Namespace":"aws-embedded-metrics","Dimensions":[],"Metrics":[{"Name":"Latency","Unit":"Milliseconds"}]}],"Timestamp":0},"Latency":100.0}"#
+/// );
+/// # }
+/// ```
+pub struct Deterministic {
+    clock: Box<dyn Clock>,
+}
+
+impl Deterministic {
+    /// Creates a serializer which reads timestamps from the given clock
+    pub fn new(clock: impl Clock + 'static) -> Deterministic {
+        Deterministic {
+            clock: Box::new(clock),
+        }
+    }
+
+    /// Creates a serializer which stamps every document with the same timestamp,
+    /// in milliseconds since the unix epoch
+    pub fn at(timestamp: u64) -> Deterministic {
+        Deterministic::new(FixedClock::from_millis(timestamp))
+    }
+}
+
+impl Serialize for Deterministic {
+    fn serialize(
+        &self,
+        mut context: MetricContext,
+    ) -> String {
+        context
+            .meta
+            .insert("Timestamp".into(), self.clock.now_millis().into());
+        render(context, true)
+    }
+}
+
+/// Renders a context as an EMF document, optionally sorting metric and dimension
+/// names which would otherwise follow hash map order
+fn render(
+    context: MetricContext,
+    sorted: bool,
+) -> String {
+    let MetricContext {
+        namespace,
+        meta,
+        properties,
+        dimensions,
+        metrics,
+        directives,
+        ..
+    } = context;
+
+    let mut target_values = BTreeMap::new();
+    let mut cloud_watch_metrics = Vec::with_capacity(directives.len() + 1);
+
+    // metrics not referenced by an explicit directive belong to the context's own
+    let referenced: HashSet<&str> = directives
+        .iter()
+        .flat_map(|directive| directive.metrics.iter().map(String::as_str))
+        .collect();
+    let default = definition(
+        &namespace,
+        &dimensions,
+        metrics
+            .keys()
+            .map(String::as_str)
+            .filter(|name| !referenced.contains(name)),
+        &metrics,
+        sorted,
+    );
+    if !default.metrics.is_empty() || directives.is_empty() {
+        insert_dimensions(&dimensions, &mut target_values);
+        cloud_watch_metrics.push(default);
+    }
+    // explicit directives are only emitted when they reference recorded metrics
+    for directive in &directives {
+        let definition = definition(
+            directive.namespace.as_deref().unwrap_or(&namespace),
+            &directive.dimensions,
+            directive.metrics.iter().map(String::as_str),
+            &metrics,
+            sorted,
         );
-        target_values.append(
-            &mut properties
+        if !definition.metrics.is_empty() {
+            insert_dimensions(&directive.dimensions, &mut target_values);
+            cloud_watch_metrics.push(definition);
+        }
+    }
+
+    target_values.extend(properties.iter().map(|(k, v)| (k.as_str(), v.to_owned())));
+    target_values.extend(
+        metrics
+            .iter()
+            .map(|(name, metric)| (name.as_str(), target_value(&metric.values))),
+    );
+
+    let payload = Payload {
+        _aws: Metadata {
+            meta: meta
                 .iter()
                 .map(|(k, v)| (k.as_str(), v.to_owned()))
                 .collect(),
-        );
+            cloud_watch_metrics,
+        },
+        target_values,
+    };
+    serde_json::to_string(&payload).unwrap()
+}
 
-        let payload = metrics.iter().fold(
-            Payload {
-                _aws: Metadata {
-                    meta: meta
-                        .iter()
-                        .map(|(k, v)| (k.as_str(), v.to_owned()))
-                        .collect(),
-                    cloud_watch_metrics: [MetricDefinition {
-                        namespace: namespace.as_str(),
-                        dimensions,
-                        metrics: Vec::with_capacity(metrics.len()),
-                    }],
-                },
-                target_values,
-            },
-            move |mut payload, (name, metric)| {
-                let MetricValues { values, unit } = metric;
-                // if there is only one metric value, unwrap it to make querying easier
-                let val: Value = if values.len() == 1 {
-                    values[0].into()
-                } else {
-                    values.to_owned().into()
-                };
-                payload.target_values.insert(name, val);
-                payload._aws.cloud_watch_metrics[0]
-                    .metrics
-                    .push(Metric { name, unit: *unit });
-                payload
-            },
+/// Adds the values of a directive's dimension sets to the payload's target members
+fn insert_dimensions<'a>(
+    dimensions: &'a [HashMap<String, String>],
+    target_values: &mut BTreeMap<&'a str, Value>,
+) {
+    for dim in dimensions {
+        target_values.extend(
+            dim.iter()
+                .map(|(key, value)| (key.as_str(), Value::from(value.as_str()))),
         );
-        serde_json::to_string(&payload).unwrap()
+    }
+}
+
+/// Builds a metric directive. Referenced metrics which were never recorded are omitted
+fn definition<'a>(
+    namespace: &'a str,
+    dimensions: &'a [HashMap<String, String>],
+    names: impl Iterator<Item = &'a str>,
+    metrics: &'a HashMap<String, MetricValues>,
+    sorted: bool,
+) -> MetricDefinition<'a> {
+    let dimensions = dimensions
+        .iter()
+        .map(|dim| {
+            let mut names: Vec<&str> = dim.keys().map(String::as_str).collect();
+            if sorted {
+                names.sort_unstable();
+            }
+            names.truncate(MAX_DIMENSIONS);
+            names
+        })
+        .collect();
+    let mut metrics: Vec<Metric> = names
+        .filter_map(|name| {
+            metrics.get(name).map(|metric| Metric {
+                name,
+                unit: metric.unit,
+            })
+        })
+        .collect();
+    if sorted {
+        metrics.sort_unstable_by(|a, b| a.name.cmp(b.name));
+    }
+    MetricDefinition {
+        namespace,
+        dimensions,
+        metrics,
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{dimensions, emf, log::Aggregation};
     use jsonschema_valid::validate;
     use std::error::Error as StdError;
 
     #[test]
     fn log_serializes_metrics() {
         let mut ctx = MetricContext::default();
-        ctx.put_metric("foo", 1, Unit::Bytes);
+        ctx.put_metric("foo", 1, Unit::Bytes).unwrap();
         println!("{}", Log.serialize(ctx));
     }
 
+    #[test]
+    fn log_serializes_compressed_values() -> Result<(), Box<dyn StdError>> {
+        let mut ctx = MetricContext::default();
+        ctx.set_aggregation(Aggregation::Compressed);
+        for value in &[1, 2, 1, 1] {
+            ctx.put_metric("foo", *value, Unit::Count)?;
+        }
+        let payload: Value = serde_json::from_str(&Log.serialize(ctx))?;
+        assert_eq!(
+            payload["foo"],
+            json!({ "Values": [1.0, 2.0], "Counts": [3, 1] })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn log_serializes_statistic_sets() -> Result<(), Box<dyn StdError>> {
+        let mut ctx = MetricContext::default();
+        for value in &[3, 1, 2] {
+            ctx.put_metric_aggregated("foo", *value, Unit::Count, Aggregation::StatisticSet)?;
+        }
+        let payload: Value = serde_json::from_str(&Log.serialize(ctx))?;
+        assert_eq!(
+            payload["foo"],
+            json!({ "Max": 3.0, "Min": 1.0, "SampleCount": 3, "Sum": 6.0 })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn log_serializes_histograms() -> Result<(), Box<dyn StdError>> {
+        let mut ctx = MetricContext::default();
+        for value in &[1, 1, 100] {
+            ctx.put_histogram("foo", *value, Unit::Milliseconds)?;
+        }
+        let payload: Value = serde_json::from_str(&Log.serialize(ctx))?;
+        assert_eq!(payload["foo"]["Values"].as_array().map(Vec::len), Some(2));
+        assert_eq!(payload["foo"]["Counts"], json!([2, 1]));
+        Ok(())
+    }
+
+    #[test]
+    fn log_serializes_multiple_directives() -> Result<(), Box<dyn StdError>> {
+        let mut ctx = MetricContext::default();
+        ctx.put_dimensions(dimensions! { "Service" => "api" })?;
+        ctx.put_metric("Latency", 1, Unit::Milliseconds)?;
+        ctx.put_metric("Charges", 2, Unit::Count)?;
+        ctx.put_directive(
+            "Billing",
+            vec![dimensions! { "Tenant" => "acme" }],
+            vec!["Charges".into()],
+        )?;
+        let payload: Value = serde_json::from_str(&Log.serialize(ctx))?;
+        assert_eq!(
+            payload["_aws"]["CloudWatchMetrics"],
+            json!([
+                {
+                    "Namespace": "aws-embedded-metrics",
+                    "Dimensions": [["Service"]],
+                    "Metrics": [{ "Name": "Latency", "Unit": "Milliseconds" }]
+                },
+                {
+                    "Namespace": "Billing",
+                    "Dimensions": [["Tenant"]],
+                    "Metrics": [{ "Name": "Charges", "Unit": "Count" }]
+                }
+            ])
+        );
+        assert_eq!(payload["Service"], "api");
+        assert_eq!(payload["Tenant"], "acme");
+        Ok(())
+    }
+
+    #[test]
+    fn log_groups_metrics_by_dimension_set() -> Result<(), Box<dyn StdError>> {
+        let mut ctx = MetricContext::default();
+        ctx.put_metric_with_dimensions("Hits", 1, Unit::Count, dimensions! { "Key" => "a" })?;
+        ctx.put_metric_with_dimensions("Misses", 1, Unit::Count, dimensions! { "Key" => "a" })?;
+        ctx.put_metric_with_dimensions("Hits", 1, Unit::Count, dimensions! { "Key" => "a" })?;
+        let payload: Value = serde_json::from_str(&Log.serialize(ctx))?;
+        let directives = payload["_aws"]["CloudWatchMetrics"].as_array().unwrap();
+        assert_eq!(directives.len(), 1);
+        assert_eq!(directives[0]["Namespace"], "aws-embedded-metrics");
+        assert_eq!(directives[0]["Dimensions"], json!([["Key"]]));
+        assert_eq!(directives[0]["Metrics"].as_array().map(Vec::len), Some(2));
+        assert_eq!(payload["Hits"], json!([1.0, 1.0]));
+        Ok(())
+    }
+
+    #[test]
+    fn log_serializes_within_limit_as_is() {
+        let mut ctx = MetricContext::default();
+        ctx.put_metric("foo", 1, Unit::Count).unwrap();
+        let (documents, errors) = Log.serialize_within(ctx, MAX_EVENT_BYTES);
+        assert_eq!(documents.len(), 1);
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn log_splits_values_across_documents() -> Result<(), Box<dyn StdError>> {
+        let mut ctx = MetricContext::default();
+        ctx.set_property("RequestId", "abc")?;
+        for value in 0..2000 {
+            ctx.put_metric("foo", value, Unit::Count)?;
+        }
+        ctx.put_metric("bar", 1, Unit::Count)?;
+        let (documents, errors) = Log.serialize_within(ctx, 4096);
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert!(documents.len() > 1);
+        let mut values = 0;
+        for document in &documents {
+            assert!(document.len() <= 4096);
+            let payload: Value = serde_json::from_str(document)?;
+            assert_eq!(payload["RequestId"], "abc");
+            values += payload["foo"].as_array().map(Vec::len).unwrap_or(1);
+        }
+        assert_eq!(values, 2000);
+        Ok(())
+    }
+
+    #[test]
+    fn log_drops_oversized_properties() -> Result<(), Box<dyn StdError>> {
+        let mut ctx = MetricContext::default();
+        ctx.set_property("Small", "ok")?;
+        ctx.set_property("Large", "x".repeat(8192))?;
+        ctx.put_metric("foo", 1, Unit::Count)?;
+        let (documents, errors) = Log.serialize_within(ctx, 4096);
+        assert_eq!(errors, vec![Error::PropertyTooLarge("Large".into())]);
+        assert_eq!(documents.len(), 1);
+        let payload: Value = serde_json::from_str(&documents[0])?;
+        assert_eq!(payload["Small"], "ok");
+        assert_eq!(payload["foo"], 1.0);
+        Ok(())
+    }
+
+    #[test]
+    fn log_splits_values_per_metric_limit() -> Result<(), Box<dyn StdError>> {
+        let mut ctx = MetricContext::default();
+        for value in 0..150 {
+            ctx.put_metric("Raw", value, Unit::Count)?;
+        }
+        let (documents, errors) = Log.serialize_within(ctx, MAX_EVENT_BYTES);
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(documents.len(), 2);
+        for document in &documents {
+            assert_eq!(emf::validate(document), Ok(()));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn log_splits_compressed_values_and_histograms() -> Result<(), Box<dyn StdError>> {
+        let mut ctx = MetricContext::default();
+        for value in 0..300 {
+            ctx.put_metric_aggregated("Compressed", value, Unit::Count, Aggregation::Compressed)?;
+            ctx.put_histogram("Histogram", value, Unit::Milliseconds)?;
+        }
+        let (documents, errors) = Log.serialize_within(ctx, 2048);
+        assert!(errors.is_empty(), "{:?}", errors);
+        let mut counts = 0;
+        for document in &documents {
+            assert!(document.len() <= 2048);
+            assert_eq!(emf::validate(document), Ok(()));
+            let payload: Value = serde_json::from_str(document)?;
+            counts += payload["Compressed"]["Counts"]
+                .as_array()
+                .map(Vec::len)
+                .unwrap_or_default();
+        }
+        assert_eq!(counts, 300);
+        Ok(())
+    }
+
+    #[test]
+    fn deterministic_serializes_identically() {
+        let payloads: Vec<String> = (0..2)
+            .map(|run| {
+                let mut ctx = MetricContext::default();
+                ctx.put_dimensions(dimensions! {
+                    "Service" => "api",
+                    "Route" => "/users",
+                    "Region" => "us-east-1"
+                })
+                .unwrap();
+                let mut names = vec!["Zeta", "Alpha", "Mid", "Beta"];
+                if run == 1 {
+                    names.reverse();
+                }
+                for name in names {
+                    ctx.put_metric(name, 1, Unit::Count).unwrap();
+                }
+                Deterministic::at(1_600_000_000_000).serialize(ctx)
+            })
+            .collect();
+        assert_eq!(payloads[0], payloads[1]);
+        assert!(payloads[0].contains(r#""Dimensions":[["Region","Route","Service"]]"#));
+        assert!(payloads[0].contains(r#""Timestamp":1600000000000"#));
+        let alpha = payloads[0].find(r#""Name":"Alpha""#).unwrap();
+        let zeta = payloads[0].find(r#""Name":"Zeta""#).unwrap();
+        assert!(alpha < zeta);
+    }
+
     #[test]
     fn log_serializes_valid_payload() -> Result<(), Box<dyn StdError>> {
         let mut ctx = MetricContext::default();
-        ctx.put_metric("foo", 1, Unit::Bytes);
+        ctx.put_metric("foo", 1, Unit::Bytes)?;
         let payload = Log.serialize(ctx);
         let result = validate(
             &serde_json::from_str(&payload)?,
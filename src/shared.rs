@@ -0,0 +1,203 @@
+//! A metric logger which can be shared between threads
+use crate::{
+    error::Error,
+    log::{Aggregation, MetricLogger, Unit},
+};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+/// A cloneable, `Send + Sync` handle to a single `MetricLogger`
+///
+/// Every clone records into the same context, so parallel work within one unit of
+/// work can record metrics without passing `&mut MetricLogger` around. The logger
+/// is flushed when the last handle is dropped, or whenever `flush` is called
+///
+/// # example
+/// ```rust,edition2018
+/// use aws_embedded_metrics::{MetricLogger, SharedMetricLogger, Unit};
+/// use std::thread;
+///
+/// # fn main() {
+/// let metrics = SharedMetricLogger::new(MetricLogger::default());
+/// let workers: Vec<_> = (0..4)
+///     .map(|_| {
+///         let metrics = metrics.clone();
+///         thread::spawn(move || metrics.increment("ItemsProcessed", 1, Unit::Count))
+///     })
+///     .collect();
+/// for worker in workers {
+///     worker.join().unwrap();
+/// }
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct SharedMetricLogger {
+    logger: Arc<Mutex<MetricLogger>>,
+}
+
+impl From<MetricLogger> for SharedMetricLogger {
+    fn from(logger: MetricLogger) -> SharedMetricLogger {
+        SharedMetricLogger::new(logger)
+    }
+}
+
+impl SharedMetricLogger {
+    /// Wraps a logger so it can be shared
+    pub fn new(logger: MetricLogger) -> Self {
+        SharedMetricLogger {
+            logger: Arc::new(Mutex::new(logger)),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, MetricLogger> {
+        self.logger
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Runs a closure with exclusive access to the underlying logger, for
+    /// recording several members at once or using its configuration methods
+    pub fn with<T>(
+        &self,
+        f: impl FnOnce(&mut MetricLogger) -> T,
+    ) -> T {
+        f(&mut self.lock())
+    }
+
+    /// Creates an independent logger from the shared one, as with `MetricLogger::child`
+    pub fn child(&self) -> MetricLogger {
+        self.lock().child()
+    }
+
+    /// Flushes the shared context to the logger's sink, as with `MetricLogger::flush`
+    pub fn flush(&self) -> Result<(), Vec<Error>> {
+        self.lock().flush()
+    }
+
+    /// Errors recorded since the last flush
+    pub fn errors(&self) -> Vec<Error> {
+        self.lock().errors().to_vec()
+    }
+
+    /// Set the CloudWatch namespace that metrics should be published to
+    pub fn set_namespace(
+        &self,
+        ns: impl Into<String>,
+    ) {
+        self.lock().set_namespace(ns)
+    }
+
+    /// Set a property, as with `MetricLogger::set_property`
+    pub fn set_property(
+        &self,
+        name: impl Into<String>,
+        value: impl Into<Value>,
+    ) -> Result<(), Error> {
+        self.lock().set_property(name, value)
+    }
+
+    /// Add a dimension set, as with `MetricLogger::put_dimensions`
+    pub fn put_dimensions(
+        &self,
+        dims: HashMap<String, String>,
+    ) -> Result<(), Error> {
+        self.lock().put_dimensions(dims)
+    }
+
+    /// Put a metric value, as with `MetricLogger::put_metric`
+    pub fn put_metric(
+        &self,
+        name: impl Into<String>,
+        value: impl Into<f64>,
+        unit: Unit,
+    ) -> Result<(), Error> {
+        self.lock().put_metric(name, value, unit)
+    }
+
+    /// Put a metric value with its own aggregation, as with `MetricLogger::put_metric_aggregated`
+    pub fn put_metric_aggregated(
+        &self,
+        name: impl Into<String>,
+        value: impl Into<f64>,
+        unit: Unit,
+        aggregation: Aggregation,
+    ) -> Result<(), Error> {
+        self.lock()
+            .put_metric_aggregated(name, value, unit, aggregation)
+    }
+
+    /// Put a metric value with its own dimensions, as with `MetricLogger::put_metric_with_dimensions`
+    pub fn put_metric_with_dimensions(
+        &self,
+        name: impl Into<String>,
+        value: impl Into<f64>,
+        unit: Unit,
+        dimensions: HashMap<String, String>,
+    ) -> Result<(), Error> {
+        self.lock()
+            .put_metric_with_dimensions(name, value, unit, dimensions)
+    }
+
+    /// Put a metric value into a histogram, as with `MetricLogger::put_histogram`
+    pub fn put_histogram(
+        &self,
+        name: impl Into<String>,
+        value: impl Into<f64>,
+        unit: Unit,
+    ) -> Result<(), Error> {
+        self.lock().put_histogram(name, value, unit)
+    }
+
+    /// Add to a counter, as with `MetricLogger::increment`
+    pub fn increment(
+        &self,
+        name: impl Into<String>,
+        by: impl Into<f64>,
+        unit: Unit,
+    ) -> Result<(), Error> {
+        self.lock().increment(name, by, unit)
+    }
+
+    /// Set a gauge, as with `MetricLogger::gauge`
+    pub fn gauge(
+        &self,
+        name: impl Into<String>,
+        value: impl Into<f64>,
+        unit: Unit,
+    ) -> Result<(), Error> {
+        self.lock().gauge(name, value, unit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MemorySink;
+    use std::thread;
+
+    #[test]
+    fn shared_logger_records_from_many_threads() {
+        let sink = MemorySink::default();
+        let metrics = SharedMetricLogger::new(MetricLogger::with_sink(sink.clone()));
+        let workers: Vec<_> = (0..8)
+            .map(|_| {
+                let metrics = metrics.clone();
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        metrics.increment("Items", 1, Unit::Count).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        drop(metrics);
+
+        assert_eq!(sink.contexts().len(), 1);
+        assert_eq!(sink.values("Items"), vec![800.0]);
+    }
+}
@@ -1,6 +1,10 @@
 //! Sinks contains interfaces and implementations for reporting metric
 //! data to an external system
-use crate::{log::MetricContext, serialize::Serialize};
+use crate::{
+    error::Error,
+    log::MetricContext,
+    serialize::{Serialize, MAX_DATAGRAM_BYTES, MAX_EVENT_BYTES},
+};
 use std::{
     convert::{TryFrom, TryInto},
     error::Error as StdError,
@@ -10,21 +14,38 @@ use std::{
 };
 use url::Url;
 
-pub(crate) trait Sink {
+/// A destination for flushed metric contexts
+pub trait Sink: Send {
+    /// Writes a flushed context, returning any errors raised while serializing or sending it
     fn accept(
         &mut self,
         context: MetricContext,
-    );
+    ) -> Result<(), Vec<Error>>;
 }
 
-pub(crate) struct Lambda(dyn Serialize);
+/// Writes serialized metrics to stdout, where the Lambda runtime forwards them to CloudWatch Logs
+pub(crate) struct Lambda(Box<dyn Serialize + Send>);
+
+impl Lambda {
+    pub(crate) fn new(serializer: impl Serialize + Send + 'static) -> Self {
+        Lambda(Box::new(serializer))
+    }
+}
 
 impl Sink for Lambda {
     fn accept(
         &mut self,
         context: MetricContext,
-    ) {
-        println!("{}", self.0.serialize(context))
+    ) -> Result<(), Vec<Error>> {
+        let (documents, errors) = self.0.serialize_within(context, MAX_EVENT_BYTES);
+        for document in documents {
+            println!("{}", document)
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
     }
 }
 
@@ -32,26 +53,57 @@ pub(crate) struct Agent {
     log_group_name: String,
     log_stream_name: Option<String>,
     transport: Transport,
-    serializer: Box<dyn Serialize + 'static>,
+    serializer: Box<dyn Serialize + Send + 'static>,
 }
 
+/// How long to wait on the agent to accept a connection
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// How long to wait on the agent to accept a write
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
 enum Transport {
-    Tcp(TcpStream),
+    /// A connection to the agent, re-established if a write fails
+    Tcp((Option<TcpStream>, SocketAddr)),
     Udp((UdpSocket, SocketAddr)),
 }
 
+fn connect(addr: &SocketAddr) -> io::Result<TcpStream> {
+    let tcp = TcpStream::connect_timeout(addr, CONNECT_TIMEOUT)?;
+    tcp.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    Ok(tcp)
+}
+
 impl Transport {
+    /// The largest payload the agent accepts over this transport, including the trailing newline
+    fn max_bytes(&self) -> usize {
+        match self {
+            Transport::Udp(_) => MAX_DATAGRAM_BYTES,
+            Transport::Tcp(_) => MAX_EVENT_BYTES,
+        }
+    }
+
     fn send(
         &mut self,
         bytes: &[u8],
-    ) {
-        // todo: communicate errs
+    ) -> io::Result<()> {
         match self {
-            Transport::Udp((stream, addr)) => {
-                drop(stream.send_to(bytes, *addr));
-            }
-            Transport::Tcp(stream) => {
-                drop(stream.write_all(bytes));
+            Transport::Udp((stream, addr)) => stream.send_to(bytes, *addr).map(drop),
+            Transport::Tcp((stream, addr)) => {
+                // a failed write may be down to a dropped connection, so it is
+                // retried once on a fresh one
+                let written = match stream {
+                    Some(tcp) => tcp.write_all(bytes),
+                    None => Err(io::ErrorKind::NotConnected.into()),
+                };
+                if written.is_ok() {
+                    return Ok(());
+                }
+                *stream = None;
+                let mut tcp = connect(addr)?;
+                tcp.write_all(bytes)?;
+                *stream = Some(tcp);
+                Ok(())
             }
         }
     }
@@ -66,15 +118,11 @@ impl TryFrom<Endpoint> for Transport {
                     .to_socket_addrs()?
                     .next()
                     .expect("failed to resolve socket address");
-                let tcp = TcpStream::connect_timeout(&addr, Duration::from_millis(50))?;
-                tcp.set_nonblocking(true)?;
-                tcp.set_write_timeout(Some(Duration::from_secs(1)))?;
-                Ok(Transport::Tcp(tcp))
+                Ok(Transport::Tcp((Some(connect(&addr)?), addr)))
             }
             Endpoint::Udp(host, port) => {
                 let udp = UdpSocket::bind("0.0.0.0:0")?;
-                udp.set_nonblocking(true)?;
-                udp.set_write_timeout(Some(Duration::from_secs(1)))?;
+                udp.set_write_timeout(Some(WRITE_TIMEOUT))?;
                 let addr = (host.as_str(), port)
                     .to_socket_addrs()?
                     .next()
@@ -117,7 +165,7 @@ impl Agent {
         log_group_name: String,
         log_stream_name: Option<String>,
         config_endpoint: Option<String>,
-        serializer: impl Serialize + 'static,
+        serializer: impl Serialize + Send + 'static,
     ) -> Result<Self, Box<dyn StdError>> {
         let ep = config_endpoint
             .and_then(Self::parse)
@@ -136,7 +184,7 @@ impl Sink for Agent {
     fn accept(
         &mut self,
         context: MetricContext,
-    ) {
+    ) -> Result<(), Vec<Error>> {
         let mut editable = context;
         editable
             .meta
@@ -147,14 +195,31 @@ impl Sink for Agent {
                 .insert("LogStreamName".into(), stream.as_str().into());
         }
 
-        let payload = self.serializer.serialize(editable);
-        self.transport.send((payload + "\n").as_bytes());
+        let (payloads, mut errors) = self
+            .serializer
+            .serialize_within(editable, self.transport.max_bytes() - 1);
+        for payload in payloads {
+            if let Err(err) = self.transport.send((payload + "\n").as_bytes()) {
+                errors.push(Error::SendFailed(err.to_string()));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{emf, log::Unit, serialize::Log};
+    use std::{
+        io::{BufRead, BufReader},
+        net::TcpListener,
+        thread,
+    };
 
     #[test]
     fn agent_parses_udp_endpoint() {
@@ -176,4 +241,84 @@ mod tests {
     fn agent_ignores_other_endpoint() {
         assert_eq!(Agent::parse("other://0.0.0.0:7890"), None)
     }
+
+    #[test]
+    fn agent_splits_payloads_into_datagrams() -> Result<(), Box<dyn StdError>> {
+        let server = UdpSocket::bind("127.0.0.1:0")?;
+        server.set_read_timeout(Some(Duration::from_secs(1)))?;
+        let mut agent = Agent::create(
+            "group".into(),
+            None,
+            Some(format!("udp://{}", server.local_addr()?)),
+            Log,
+        )?;
+        // roughly 100 KiB of values, more than a single datagram can carry
+        let mut context = MetricContext::default();
+        for metric in 0..80 {
+            for value in 0..100 {
+                context.put_metric(
+                    format!("Metric{}", metric),
+                    value as f64 + 0.123_456_789,
+                    Unit::Count,
+                )?;
+            }
+        }
+        assert_eq!(agent.accept(context), Ok(()));
+
+        let mut buf = vec![0; MAX_DATAGRAM_BYTES + 1];
+        let (mut received, mut values) = (0, 0);
+        while values < 8000 {
+            let len = server.recv(&mut buf)?;
+            assert!(len <= MAX_DATAGRAM_BYTES);
+            let datagram = std::str::from_utf8(&buf[..len])?;
+            assert!(datagram.ends_with('\n'));
+            let document = emf::parse(datagram.trim_end())?;
+            values += document
+                .metrics
+                .values()
+                .map(|value| match value {
+                    emf::MetricValue::Many(values) => values.len(),
+                    _ => 1,
+                })
+                .sum::<usize>();
+            received += len;
+        }
+        assert!(received > 96 * 1024, "received {} bytes", received);
+        Ok(())
+    }
+
+    #[test]
+    fn agent_reconnects_over_tcp() -> Result<(), Box<dyn StdError>> {
+        let server = TcpListener::bind("127.0.0.1:0")?;
+        let mut agent = Agent::create(
+            "group".into(),
+            None,
+            Some(format!("tcp://{}", server.local_addr()?)),
+            Log,
+        )?;
+        // the agent goes away after the first connection
+        drop(server.accept()?);
+        let reconnected = thread::spawn(move || -> io::Result<String> {
+            let (stream, _) = server.accept()?;
+            let mut line = String::new();
+            BufReader::new(stream).read_line(&mut line)?;
+            Ok(line)
+        });
+
+        // writes into the closed connection may succeed until the peer resets it
+        for _ in 0..100 {
+            let mut context = MetricContext::default();
+            context.put_metric("Requests", 1, Unit::Count)?;
+            assert_eq!(agent.accept(context), Ok(()));
+            if reconnected.is_finished() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let line = reconnected.join().expect("server panicked")?;
+        assert!(emf::parse(line.trim_end())?
+            .metrics
+            .contains_key("Requests"));
+        Ok(())
+    }
 }
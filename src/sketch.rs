@@ -0,0 +1,316 @@
+//! A mergeable quantile sketch with relative accuracy guarantees
+//!
+//! Values are mapped onto logarithmically sized buckets so that any quantile
+//! computed from the sketch is within the configured relative accuracy of the
+//! true value. See [DDSketch](https://arxiv.org/abs/1908.10693) for more information
+use crate::{error::Error, serialize::MAX_VALUES};
+use std::{collections::BTreeMap, mem};
+
+/// Relative accuracy used for histograms when none is specified
+pub(crate) const DEFAULT_RELATIVE_ACCURACY: f64 = 0.01;
+
+/// Magnitudes smaller than this are counted as zero
+const MIN_INDEXABLE: f64 = 1e-9;
+
+/// A histogram of recorded values from which approximate quantiles can be derived
+///
+/// Sketches keep at most 100 buckets by default, the number of values a metric may
+/// hold. Past that the lowest buckets are merged together, so only quantiles among
+/// the lowest values lose their accuracy guarantee
+///
+/// # example
+/// ```rust,edition2018
+/// use aws_embedded_metrics::Sketch;
+///
+/// # fn main() {
+/// let mut sketch = Sketch::new(0.01).unwrap();
+/// for latency in 1..=100 {
+///     sketch.add(latency as f64).unwrap();
+/// }
+/// assert!(sketch.quantile(0.99).unwrap() > 97.0);
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sketch {
+    relative_accuracy: f64,
+    gamma: f64,
+    ln_gamma: f64,
+    positive: BTreeMap<i32, u64>,
+    negative: BTreeMap<i32, u64>,
+    zero_count: u64,
+    count: u64,
+    max_buckets: usize,
+}
+
+impl Sketch {
+    /// Creates a new sketch, failing when `relative_accuracy` is not between 0 and 1
+    pub fn new(relative_accuracy: f64) -> Result<Self, Error> {
+        if !(relative_accuracy > 0.0 && relative_accuracy < 1.0) {
+            return Err(Error::InvalidRelativeAccuracy(relative_accuracy));
+        }
+        let gamma = (1.0 + relative_accuracy) / (1.0 - relative_accuracy);
+        Ok(Sketch {
+            relative_accuracy,
+            gamma,
+            ln_gamma: gamma.ln(),
+            positive: BTreeMap::new(),
+            negative: BTreeMap::new(),
+            zero_count: 0,
+            count: 0,
+            max_buckets: MAX_VALUES,
+        })
+    }
+
+    /// Sets the number of buckets kept before the lowest are merged, at least one
+    pub fn with_max_buckets(
+        mut self,
+        max_buckets: usize,
+    ) -> Self {
+        self.max_buckets = max_buckets.max(1);
+        self.collapse();
+        self
+    }
+
+    fn buckets(&self) -> usize {
+        self.negative.len() + usize::from(self.zero_count > 0) + self.positive.len()
+    }
+
+    /// Merges the lowest buckets into their neighbours until at most `max_buckets` remain
+    fn collapse(&mut self) {
+        while self.buckets() > self.max_buckets {
+            let n = if let Some((_, n)) = self.negative.pop_last() {
+                n
+            } else if self.zero_count > 0 {
+                mem::take(&mut self.zero_count)
+            } else {
+                self.positive.pop_first().map_or(0, |(_, n)| n)
+            };
+            if let Some(next) = self.negative.values_mut().next_back() {
+                *next += n;
+            } else if self.zero_count > 0 {
+                self.zero_count += n;
+            } else if let Some(next) = self.positive.values_mut().next() {
+                *next += n;
+            }
+        }
+    }
+
+    fn index(
+        &self,
+        value: f64,
+    ) -> i32 {
+        (value.ln() / self.ln_gamma).ceil() as i32
+    }
+
+    fn bucket_value(
+        &self,
+        index: i32,
+    ) -> f64 {
+        2.0 * self.gamma.powi(index) / (self.gamma + 1.0)
+    }
+
+    /// Records a value, failing when it is NaN or infinite
+    pub fn add(
+        &mut self,
+        value: f64,
+    ) -> Result<(), Error> {
+        if !value.is_finite() {
+            return Err(Error::NonFiniteValue(value));
+        }
+        self.add_n(value, 1);
+        Ok(())
+    }
+
+    fn add_n(
+        &mut self,
+        value: f64,
+        n: u64,
+    ) {
+        if n == 0 {
+            return;
+        }
+        if value > MIN_INDEXABLE {
+            *self.positive.entry(self.index(value)).or_default() += n;
+        } else if value < -MIN_INDEXABLE {
+            *self.negative.entry(self.index(-value)).or_default() += n;
+        } else {
+            self.zero_count += n;
+        }
+        self.count += n;
+        self.collapse();
+    }
+
+    /// Merges the contents of another sketch into this one.
+    ///
+    /// Sketches with a different relative accuracy are merged by re-recording
+    /// their bucket values, which retains this sketch's accuracy guarantee
+    /// relative to the other sketch's representative values
+    pub fn merge(
+        &mut self,
+        other: &Sketch,
+    ) {
+        if self.relative_accuracy.to_bits() == other.relative_accuracy.to_bits() {
+            for (index, n) in &other.positive {
+                *self.positive.entry(*index).or_default() += n;
+            }
+            for (index, n) in &other.negative {
+                *self.negative.entry(*index).or_default() += n;
+            }
+            self.zero_count += other.zero_count;
+            self.count += other.count;
+            self.collapse();
+        } else {
+            let (values, counts) = other.values_counts();
+            for (value, n) in values.into_iter().zip(counts) {
+                self.add_n(value, n);
+            }
+        }
+    }
+
+    /// Total number of recorded values
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Returns the approximate value at quantile `q`, between 0 and 1
+    pub fn quantile(
+        &self,
+        q: f64,
+    ) -> Option<f64> {
+        if self.count == 0 || !(0.0..=1.0).contains(&q) {
+            return None;
+        }
+        let rank = (q * (self.count - 1) as f64) as u64;
+        let (values, counts) = self.values_counts();
+        let mut seen = 0;
+        for (value, n) in values.into_iter().zip(counts) {
+            seen += n;
+            if seen > rank {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    /// Representative bucket values, in ascending order, paired with their counts
+    pub fn values_counts(&self) -> (Vec<f64>, Vec<u64>) {
+        let buckets = self
+            .negative
+            .iter()
+            .rev()
+            .map(|(index, n)| (-self.bucket_value(*index), *n))
+            .chain(Some((0.0, self.zero_count)).filter(|(_, n)| *n > 0))
+            .chain(
+                self.positive
+                    .iter()
+                    .map(|(index, n)| (self.bucket_value(*index), *n)),
+            );
+        buckets.unzip()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn within(
+        actual: f64,
+        expected: f64,
+        accuracy: f64,
+    ) -> bool {
+        (actual - expected).abs() <= expected.abs() * accuracy
+    }
+
+    #[test]
+    fn sketch_quantiles_are_relatively_accurate() {
+        let mut sketch = Sketch::new(0.01).unwrap();
+        for value in 1..=1000 {
+            sketch.add(value as f64).unwrap();
+        }
+        assert_eq!(sketch.count(), 1000);
+        for (q, expected) in &[(0.5, 500.0), (0.9, 900.0), (0.99, 990.0)] {
+            let actual = sketch.quantile(*q).unwrap();
+            assert!(
+                within(actual, *expected, 0.01),
+                "p{} was {}, expected {}",
+                q,
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn sketch_orders_negative_zero_and_positive_values() {
+        let mut sketch = Sketch::new(0.01).unwrap();
+        for value in &[5.0, 0.0, -5.0, 5.0] {
+            sketch.add(*value).unwrap();
+        }
+        let (values, counts) = sketch.values_counts();
+        assert_eq!(values.len(), 3);
+        assert!(within(values[0], -5.0, 0.01));
+        assert_eq!(values[1], 0.0);
+        assert!(within(values[2], 5.0, 0.01));
+        assert_eq!(counts, vec![1, 1, 2]);
+    }
+
+    #[test]
+    fn sketch_collapses_lowest_buckets() {
+        let mut sketch = Sketch::new(0.01).unwrap();
+        for value in 1..5000 {
+            sketch.add(value as f64).unwrap();
+        }
+        let (values, counts) = sketch.values_counts();
+        assert!(values.len() <= MAX_VALUES);
+        assert_eq!(counts.iter().sum::<u64>(), 4999);
+        assert!(within(sketch.quantile(0.99).unwrap(), 4949.0, 0.01));
+
+        let mut sketch = Sketch::new(0.01).unwrap().with_max_buckets(2);
+        for value in &[-5.0, 0.0, 5.0, 50.0] {
+            sketch.add(*value).unwrap();
+        }
+        let (values, counts) = sketch.values_counts();
+        assert_eq!(values.len(), 2);
+        assert!(within(values[1], 50.0, 0.01));
+        assert_eq!(counts, vec![3, 1]);
+    }
+
+    #[test]
+    fn sketch_merges() {
+        let (mut a, mut b) = (Sketch::new(0.01).unwrap(), Sketch::new(0.01).unwrap());
+        a.add(1.0).unwrap();
+        b.add(1.0).unwrap();
+        b.add(100.0).unwrap();
+        a.merge(&b);
+        assert_eq!(a.count(), 3);
+        assert_eq!(a.values_counts().1, vec![2, 1]);
+    }
+
+    #[test]
+    fn sketch_merges_differing_accuracy() {
+        let (mut a, mut b) = (Sketch::new(0.01).unwrap(), Sketch::new(0.05).unwrap());
+        b.add(100.0).unwrap();
+        a.merge(&b);
+        assert_eq!(a.count(), 1);
+        assert!(within(a.quantile(0.5).unwrap(), 100.0, 0.06));
+    }
+
+    #[test]
+    fn sketch_rejects_invalid_accuracy() {
+        for accuracy in &[0.0, 1.0, -0.5, f64::NAN] {
+            assert!(matches!(
+                Sketch::new(*accuracy),
+                Err(Error::InvalidRelativeAccuracy(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn sketch_rejects_non_finite_values() {
+        let mut sketch = Sketch::new(0.01).unwrap();
+        for value in &[f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            assert!(matches!(sketch.add(*value), Err(Error::NonFiniteValue(_))));
+        }
+        assert_eq!(sketch.count(), 0);
+    }
+}
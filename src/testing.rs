@@ -0,0 +1,253 @@
+//! Utilities for asserting on the metrics your code records
+//!
+//! # example
+//! ```rust,edition2018
+//! use aws_embedded_metrics::{
+//!     assert_dimension, assert_metric, dimensions, testing::MemorySink, MetricLogger, Unit,
+//! };
+//!
+//! # fn main() {
+//! let sink = MemorySink::default();
+//! {
+//!     let mut metrics = MetricLogger::with_sink(sink.clone());
+//!     metrics.put_dimensions(dimensions! { "Service" => "Aggregator" });
+//!     metrics.put_metric("ProcessingLatency", 100, Unit::Milliseconds);
+//!     metrics.set_property("RequestId", "422b1569-16f6-4a03-b8f0-fe3fd9b100f8");
+//! }
+//! assert_metric!(sink, "ProcessingLatency", Unit::Milliseconds);
+//! assert_dimension!(sink, "Service", "Aggregator");
+//! assert_eq!(sink.values("ProcessingLatency"), vec![100.0]);
+//! assert!(sink.property("RequestId").is_some());
+//! # }
+//! ```
+use crate::{
+    emf::{self, Document},
+    error::Error,
+    log::{MetricContext, Unit, Values},
+    serialize::{Log, Serialize},
+    sink::Sink,
+};
+use serde_json::Value;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// A sink which keeps every flushed context in memory.
+///
+/// Clones share the same captured contexts, so keep a clone to inspect
+/// after handing one to a `MetricLogger` or `MetricsRegistry`
+#[derive(Clone, Default)]
+pub struct MemorySink {
+    contexts: Arc<Mutex<Vec<MetricContext>>>,
+}
+
+impl Sink for MemorySink {
+    fn accept(
+        &mut self,
+        context: MetricContext,
+    ) -> Result<(), Vec<Error>> {
+        self.lock().push(context);
+        Ok(())
+    }
+}
+
+impl MemorySink {
+    fn lock(&self) -> MutexGuard<'_, Vec<MetricContext>> {
+        self.contexts
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Every context flushed so far, in the order they were flushed
+    pub fn contexts(&self) -> Vec<MetricContext> {
+        self.lock().clone()
+    }
+
+    /// Every flushed context serialized as an embedded metric format document
+    pub fn payloads(&self) -> Vec<String> {
+        self.payloads_with(&Log)
+    }
+
+    /// Every flushed context serialized with the given serializer, such as
+    /// `Deterministic` for comparing payloads against golden files
+    pub fn payloads_with(
+        &self,
+        serializer: &impl Serialize,
+    ) -> Vec<String> {
+        self.contexts()
+            .into_iter()
+            .map(|context| serializer.serialize(context))
+            .collect()
+    }
+
+    /// Every flushed context serialized and parsed back into a typed document
+    pub fn documents(&self) -> Vec<Document> {
+        self.payloads()
+            .iter()
+            .filter_map(|payload| emf::parse(payload).ok())
+            .collect()
+    }
+
+    /// Discards all captured contexts
+    pub fn clear(&self) {
+        self.lock().clear()
+    }
+
+    /// The unit a metric was last recorded with, if it was recorded at all
+    pub fn unit(
+        &self,
+        name: &str,
+    ) -> Option<Unit> {
+        self.lock()
+            .iter()
+            .rev()
+            .find_map(|context| context.metrics.get(name))
+            .map(|metric| metric.unit)
+    }
+
+    /// All values recorded for a metric across flushes.
+    ///
+    /// Compressed values and histograms are expanded by their counts, with
+    /// histograms contributing their approximate bucket values. Counters and gauges
+    /// contribute their single accumulated value. Statistic sets retain no individual
+    /// values and contribute none
+    pub fn values(
+        &self,
+        name: &str,
+    ) -> Vec<f64> {
+        let mut recorded = Vec::new();
+        for metric in self
+            .lock()
+            .iter()
+            .filter_map(|context| context.metrics.get(name))
+        {
+            let (values, counts) = match &metric.values {
+                Values::Raw(values) => (values.clone(), vec![1; values.len()]),
+                Values::Compressed { values, counts } => (values.clone(), counts.clone()),
+                Values::Histogram(sketch) => sketch.values_counts(),
+                Values::Sum(value) | Values::Last(value) => (vec![*value], vec![1]),
+                Values::StatisticSet { .. } => continue,
+            };
+            for (value, count) in values.into_iter().zip(counts) {
+                recorded.extend((0..count).map(|_| value));
+            }
+        }
+        recorded
+    }
+
+    /// The value a dimension was last recorded with in any dimension set
+    pub fn dimension(
+        &self,
+        name: &str,
+    ) -> Option<String> {
+        self.lock().iter().rev().find_map(|context| {
+            context
+                .dimensions
+                .iter()
+                .chain(
+                    context
+                        .directives
+                        .iter()
+                        .flat_map(|directive| directive.dimensions.iter()),
+                )
+                .find_map(|dims| dims.get(name))
+                .cloned()
+        })
+    }
+
+    /// The value a property was last set to
+    pub fn property(
+        &self,
+        name: &str,
+    ) -> Option<Value> {
+        self.lock()
+            .iter()
+            .rev()
+            .find_map(|context| context.properties.get(name))
+            .cloned()
+    }
+}
+
+/// Asserts that a `MemorySink` captured a metric, optionally with a given unit
+#[macro_export]
+macro_rules! assert_metric {
+    ($sink:expr, $name:expr) => {
+        assert!(
+            $sink.unit($name).is_some(),
+            "expected metric {} to have been recorded",
+            $name
+        )
+    };
+    ($sink:expr, $name:expr, $unit:expr) => {
+        match $sink.unit($name) {
+            Some(unit) => assert_eq!(
+                unit, $unit,
+                "expected metric {} to have been recorded in {:?}",
+                $name, $unit
+            ),
+            None => panic!("expected metric {} to have been recorded", $name),
+        }
+    };
+}
+
+/// Asserts that a `MemorySink` captured a dimension, optionally with a given value
+#[macro_export]
+macro_rules! assert_dimension {
+    ($sink:expr, $name:expr) => {
+        assert!(
+            $sink.dimension($name).is_some(),
+            "expected dimension {} to have been recorded",
+            $name
+        )
+    };
+    ($sink:expr, $name:expr, $value:expr) => {
+        assert_eq!(
+            $sink.dimension($name).as_deref(),
+            Some($value),
+            "expected dimension {} to have been recorded as {}",
+            $name,
+            $value
+        )
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{dimensions, log::Aggregation, Deterministic, MetricLogger};
+
+    #[test]
+    fn memory_sink_captures_flushed_contexts() {
+        let sink = MemorySink::default();
+        let mut metrics = MetricLogger::with_sink(sink.clone());
+        metrics
+            .put_metric_aggregated("Hits", 1, Unit::Count, Aggregation::Compressed)
+            .unwrap();
+        metrics
+            .put_metric_aggregated("Hits", 1, Unit::Count, Aggregation::Compressed)
+            .unwrap();
+        assert_eq!(metrics.flush(), Ok(()));
+        metrics
+            .put_metric_with_dimensions("Misses", 1, Unit::Count, dimensions! { "Key" => "a" })
+            .unwrap();
+        drop(metrics);
+
+        assert_eq!(sink.contexts().len(), 2);
+        assert_eq!(sink.documents().len(), 2);
+        assert_eq!(sink.values("Hits"), vec![1.0, 1.0]);
+        assert_metric!(sink, "Misses", Unit::Count);
+        assert_dimension!(sink, "Key", "a");
+        assert_eq!(sink.unit("Other"), None);
+        assert_eq!(
+            sink.payloads_with(&Deterministic::at(0))[0],
+            r#"{"_aws":{"CloudWatchMetrics":[{"Namespace":"aws-embedded-metrics","Dimensions":[],"Metrics":[{"Name":"Hits","Unit":"Count"}]}],"Timestamp":0},"Hits":{"Counts":[2],"Values":[1.0]}}"#
+        );
+        sink.clear();
+        assert!(sink.payloads().is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "expected metric Latency to have been recorded")]
+    fn assert_metric_fails_for_missing_metrics() {
+        let sink = MemorySink::default();
+        assert_metric!(sink, "Latency", Unit::Milliseconds);
+    }
+}
@@ -0,0 +1,197 @@
+//! Measures durations into metrics
+use crate::{
+    clock::Clock,
+    log::{MetricLogger, Unit},
+};
+use std::{
+    future::Future,
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+/// Converts a duration to a value in the given time unit.
+/// Units other than seconds, milliseconds and microseconds fall back to milliseconds
+fn measure(
+    elapsed: Duration,
+    unit: Unit,
+) -> (f64, Unit) {
+    match unit {
+        Unit::Seconds => (elapsed.as_secs_f64(), unit),
+        Unit::Microseconds => (elapsed.as_secs_f64() * 1_000_000.0, unit),
+        _ => (elapsed.as_secs_f64() * 1_000.0, Unit::Milliseconds),
+    }
+}
+
+/// Records the time elapsed since it was started as a metric when dropped
+///
+/// Timers dereference to the logger they were started from, so you can keep
+/// recording metrics while one is running
+///
+/// # example
+/// ```rust,edition2018
+/// use aws_embedded_metrics::{metric_scope, Unit};
+///
+/// # fn main() {
+/// metric_scope(|metrics| {
+///     let mut timer = metrics.start_timer("ProcessingLatency").with_unit(Unit::Microseconds);
+///     timer.put_metric("Items", 10, Unit::Count);
+/// });
+/// # }
+/// ```
+pub struct Timer<'a> {
+    logger: &'a mut MetricLogger,
+    name: String,
+    unit: Unit,
+    clock: Arc<dyn Clock>,
+    started: Duration,
+    stopped: bool,
+}
+
+impl<'a> Timer<'a> {
+    pub(crate) fn new(
+        logger: &'a mut MetricLogger,
+        name: String,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        Timer {
+            logger,
+            name,
+            unit: Unit::Milliseconds,
+            started: clock.now(),
+            clock,
+            stopped: false,
+        }
+    }
+
+    /// Sets the unit the elapsed time is recorded in. Only `Unit::Seconds`,
+    /// `Unit::Milliseconds` and `Unit::Microseconds` are honored, others record milliseconds
+    pub fn with_unit(
+        mut self,
+        unit: Unit,
+    ) -> Self {
+        self.unit = unit;
+        self
+    }
+
+    /// Time elapsed since the timer was started
+    pub fn elapsed(&self) -> Duration {
+        self.clock.now().saturating_sub(self.started)
+    }
+
+    /// Records the elapsed time now rather than when the timer is dropped
+    pub fn stop(mut self) -> Duration {
+        self.record()
+    }
+
+    fn record(&mut self) -> Duration {
+        let elapsed = self.elapsed();
+        if !self.stopped {
+            self.stopped = true;
+            let (value, unit) = measure(elapsed, self.unit);
+            let _ = self.logger.put_metric(self.name.clone(), value, unit);
+        }
+        elapsed
+    }
+}
+
+impl Drop for Timer<'_> {
+    fn drop(&mut self) {
+        self.record();
+    }
+}
+
+impl Deref for Timer<'_> {
+    type Target = MetricLogger;
+
+    fn deref(&self) -> &MetricLogger {
+        self.logger
+    }
+}
+
+impl DerefMut for Timer<'_> {
+    fn deref_mut(&mut self) -> &mut MetricLogger {
+        self.logger
+    }
+}
+
+/// A future which records the time taken to complete it as a metric
+///
+/// Time is measured from when the future is created. Futures dropped before they
+/// complete record nothing
+pub struct Timed<'a, F> {
+    future: Pin<Box<F>>,
+    logger: &'a mut MetricLogger,
+    name: String,
+    unit: Unit,
+    clock: Arc<dyn Clock>,
+    started: Duration,
+}
+
+impl<'a, F> Timed<'a, F> {
+    pub(crate) fn new(
+        logger: &'a mut MetricLogger,
+        name: String,
+        clock: Arc<dyn Clock>,
+        future: F,
+    ) -> Self {
+        Timed {
+            future: Box::pin(future),
+            logger,
+            name,
+            unit: Unit::Milliseconds,
+            started: clock.now(),
+            clock,
+        }
+    }
+
+    /// Sets the unit the elapsed time is recorded in, as with `Timer::with_unit`
+    pub fn with_unit(
+        mut self,
+        unit: Unit,
+    ) -> Self {
+        self.unit = unit;
+        self
+    }
+}
+
+impl<F: Future> Future for Timed<'_, F> {
+    type Output = F::Output;
+
+    fn poll(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<F::Output> {
+        // every field is either boxed or Unpin, so Timed itself is Unpin
+        let this = self.get_mut();
+        let output = match this.future.as_mut().poll(cx) {
+            Poll::Ready(output) => output,
+            Poll::Pending => return Poll::Pending,
+        };
+        let (value, unit) = measure(this.clock.now().saturating_sub(this.started), this.unit);
+        let _ = this.logger.put_metric(this.name.clone(), value, unit);
+        Poll::Ready(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measure_converts_time_units() {
+        let elapsed = Duration::from_millis(1500);
+        assert_eq!(measure(elapsed, Unit::Seconds), (1.5, Unit::Seconds));
+        assert_eq!(
+            measure(elapsed, Unit::Milliseconds),
+            (1500.0, Unit::Milliseconds)
+        );
+        assert_eq!(
+            measure(elapsed, Unit::Microseconds),
+            (1_500_000.0, Unit::Microseconds)
+        );
+        assert_eq!(measure(elapsed, Unit::Bytes), (1500.0, Unit::Milliseconds));
+    }
+}
@@ -0,0 +1,212 @@
+//! Checks names and values against the limits CloudWatch places on them
+use std::borrow::Cow;
+
+/// Dimension names may be at most 255 characters
+pub(crate) const MAX_DIMENSION_NAME_LEN: usize = 255;
+
+/// Dimension values may be at most 1024 characters
+pub(crate) const MAX_DIMENSION_VALUE_LEN: usize = 1024;
+
+/// Metric names may be at most 1024 characters
+pub(crate) const MAX_METRIC_NAME_LEN: usize = 1024;
+
+/// Property values may be at most 32 KiB once serialized, leaving room for metrics
+/// in the smallest payload, a UDP datagram
+pub(crate) const MAX_PROPERTY_VALUE_BYTES: usize = 32 * 1024;
+
+/// Options for normalizing metric names before they are validated
+///
+/// # example
+/// ```rust,edition2018
+/// use aws_embedded_metrics::Normalization;
+///
+/// # fn main() {
+/// let normalization = Normalization {
+///     trim: true,
+///     whitespace: Some('_'),
+///     truncate: true,
+/// };
+/// # }
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Normalization {
+    /// Remove leading and trailing whitespace
+    pub trim: bool,
+    /// Replace whitespace within names with this character
+    pub whitespace: Option<char>,
+    /// Truncate names which exceed the maximum length
+    pub truncate: bool,
+}
+
+/// Returns a normalized and valid form of a metric name, or a reason it is invalid
+pub(crate) fn metric_name(
+    input: &str,
+    normalization: Normalization,
+) -> Result<Cow<'_, str>, &'static str> {
+    let mut output: Cow<'_, str> = if normalization.trim {
+        input.trim().into()
+    } else {
+        input.into()
+    };
+    if let Some(replacement) = normalization.whitespace {
+        if output.chars().any(char::is_whitespace) {
+            output = output
+                .chars()
+                .map(|c| if c.is_whitespace() { replacement } else { c })
+                .collect::<String>()
+                .into();
+        }
+    }
+    if output.is_empty() {
+        return Err("is empty");
+    }
+    if output.chars().count() > MAX_METRIC_NAME_LEN {
+        if !normalization.truncate {
+            return Err("is too long");
+        }
+        output = output
+            .chars()
+            .take(MAX_METRIC_NAME_LEN)
+            .collect::<String>()
+            .into();
+    }
+    Ok(output)
+}
+
+/// Returns a reason a property value is invalid
+pub(crate) fn property_value(value: &serde_json::Value) -> Result<(), &'static str> {
+    if value.to_string().len() > MAX_PROPERTY_VALUE_BYTES {
+        return Err("is larger than 32 KiB");
+    }
+    Ok(())
+}
+
+/// Options for making invalid dimension names and values valid rather than rejecting them
+///
+/// # example
+/// ```rust,edition2018
+/// use aws_embedded_metrics::Sanitization;
+///
+/// # fn main() {
+/// let sanitization = Sanitization {
+///     truncate: true,
+///     replacement: Some('_'),
+/// };
+/// # }
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Sanitization {
+    /// Truncate names and values which exceed the maximum length
+    pub truncate: bool,
+    /// Replace non-ASCII characters and leading colons with this character
+    pub replacement: Option<char>,
+}
+
+/// Returns a valid form of a dimension name or value, or a reason it could not be made valid
+pub(crate) fn dimension<'a>(
+    input: &'a str,
+    max_len: usize,
+    sanitization: Sanitization,
+) -> Result<Cow<'a, str>, &'static str> {
+    if input.is_empty() {
+        return Err("is empty");
+    }
+    let invalid = |idx: usize, c: char| !c.is_ascii() || (idx == 0 && c == ':');
+    let mut output: Cow<'a, str> = input.into();
+    if input.char_indices().any(|(idx, c)| invalid(idx, c)) {
+        match sanitization.replacement {
+            Some(replacement) if !invalid(0, replacement) => {
+                output = input
+                    .char_indices()
+                    .map(|(idx, c)| if invalid(idx, c) { replacement } else { c })
+                    .collect::<String>()
+                    .into();
+            }
+            _ if input.starts_with(':') => return Err("starts with a colon"),
+            _ => return Err("contains non-ASCII characters"),
+        }
+    }
+    // output is ASCII by now so its length in bytes is its length in characters
+    if output.len() > max_len {
+        if !sanitization.truncate {
+            return Err("is too long");
+        }
+        output = match output {
+            Cow::Borrowed(borrowed) => Cow::Borrowed(&borrowed[..max_len]),
+            Cow::Owned(mut owned) => {
+                owned.truncate(max_len);
+                Cow::Owned(owned)
+            }
+        };
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metric_name_rejects_invalid_names() {
+        assert_eq!(metric_name("", Normalization::default()), Err("is empty"));
+        assert_eq!(
+            metric_name(&"x".repeat(1025), Normalization::default()),
+            Err("is too long")
+        );
+        assert_eq!(
+            metric_name(" Latency ", Normalization::default()),
+            Ok(" Latency ".into())
+        );
+    }
+
+    #[test]
+    fn metric_name_normalizes_names() {
+        let normalization = Normalization {
+            trim: true,
+            whitespace: Some('_'),
+            truncate: true,
+        };
+        assert_eq!(
+            metric_name("  Request Latency\t", normalization),
+            Ok("Request_Latency".into())
+        );
+        assert_eq!(metric_name("   ", normalization), Err("is empty"));
+        assert_eq!(
+            metric_name(&"x".repeat(1025), normalization).map(|name| name.len()),
+            Ok(MAX_METRIC_NAME_LEN)
+        );
+    }
+
+    #[test]
+    fn dimension_accepts_valid_input() {
+        assert_eq!(
+            dimension("Service", 255, Sanitization::default()),
+            Ok("Service".into())
+        );
+    }
+
+    #[test]
+    fn dimension_rejects_invalid_input() {
+        for (input, reason) in &[
+            ("", "is empty"),
+            (":Service", "starts with a colon"),
+            ("Sérvice", "contains non-ASCII characters"),
+            ("Services", "is too long"),
+        ] {
+            assert_eq!(dimension(input, 7, Sanitization::default()), Err(*reason));
+        }
+    }
+
+    #[test]
+    fn dimension_sanitizes_invalid_input() {
+        let sanitization = Sanitization {
+            truncate: true,
+            replacement: Some('_'),
+        };
+        assert_eq!(
+            dimension(":Sérvices", 7, sanitization),
+            Ok("_S_rvic".into())
+        );
+        assert_eq!(dimension("", 7, sanitization), Err("is empty"));
+    }
+}